@@ -0,0 +1,110 @@
+//! `--breaker-failures` circuit breaker, stopping requests to Tokopedia for a while once several
+//! scrapes in a row failed.
+//!
+//! Blocked scrapers tend to stay blocked for longer the more they keep asking. The breaker state is
+//! retained on the broker so it carries over runs & products, and shown in HA as a problem sensor.
+//! Once the cooldown is over, the next scrape is let through and either closes the breaker or
+//! opens it for another cooldown.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+use rumqttc::QoS;
+use serde_json::{Value, json};
+use tkpd_tracker::{Publisher, TrackerError, discovery_lint, ha};
+
+pub struct Breaker {
+    failures: u32,
+    open_until: Option<DateTime<Utc>>,
+}
+
+impl Breaker {
+    /// Reads the state the last run left, closed when there is none
+    pub async fn read(publisher: &Publisher) -> Result<Self, TrackerError> {
+        let retained = publisher
+            .read_retained(&[ha::BREAKER_TOPIC.to_string()])
+            .await?;
+        let state: Value = retained
+            .get(ha::BREAKER_TOPIC)
+            .and_then(|state| serde_json::from_str(state).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            failures: state["failures"]
+                .as_u64()
+                .and_then(|failures| u32::try_from(failures).ok())
+                .unwrap_or(0),
+            open_until: state["open_until"]
+                .as_str()
+                .and_then(|until| DateTime::parse_from_rfc3339(until).ok())
+                .map(|until| until.with_timezone(&Utc)),
+        })
+    }
+
+    /// When requests may be made again, `None` when they may be now
+    pub fn open_until(&self) -> Option<DateTime<Utc>> {
+        self.open_until.filter(|until| *until > Utc::now())
+    }
+
+    /// Counts the outcome of a fetch, opening the breaker for `cooldown` once `max_failures` failed
+    /// in a row. Only a change is published
+    pub async fn record(
+        &mut self,
+        publisher: &Publisher,
+        discovery_prefix: &str,
+        error: Option<&TrackerError>,
+        max_failures: u32,
+        cooldown: TimeDelta,
+    ) -> Result<(), TrackerError> {
+        let before = (self.failures, self.open_until);
+        if error.is_some_and(counts) {
+            self.failures += 1;
+            if self.failures >= max_failures {
+                let until = Utc::now() + cooldown;
+                warn!(
+                    "{} scrapes failed in a row, pausing requests to Tokopedia until {until}",
+                    self.failures
+                );
+                self.open_until = Some(until);
+            }
+        } else if error.is_none() {
+            if self.open_until.is_some() {
+                info!("Tokopedia answers again, resuming requests");
+            }
+            self.failures = 0;
+            self.open_until = None;
+        }
+        if (self.failures, self.open_until) == before {
+            return Ok(());
+        }
+
+        let config = ha::breaker_config();
+        discovery_lint::lint_payload(&config).map_err(|problems| TrackerError::Discovery {
+            key: "breaker".to_string(),
+            problems,
+        })?;
+        let state = json!({
+            "state": if self.open_until.is_some() { "open" } else { "closed" },
+            "marketplace": "tokopedia",
+            "failures": self.failures,
+            "open_until": self.open_until.map(|until| until.to_rfc3339()),
+        });
+        let client = publisher.client();
+        client
+            .publish(
+                format!("{discovery_prefix}/binary_sensor/tkpdprice-tracker/breaker/config"),
+                QoS::AtLeastOnce,
+                true,
+                config.to_string(),
+            )
+            .await?;
+        client
+            .publish(ha::BREAKER_TOPIC, QoS::AtLeastOnce, true, state.to_string())
+            .await?;
+        Ok(())
+    }
+}
+
+/// Whether an error is Tokopedia refusing or failing the request, rather than the product or the
+/// tracker being at fault
+const fn counts(error: &TrackerError) -> bool {
+    matches!(error, TrackerError::Http(_) | TrackerError::GraphQl(_))
+}
@@ -0,0 +1,99 @@
+//! iCalendar feed of upcoming campaign end times, built from an exported tracker state.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// Renders the campaigns of every tracked product that haven't ended yet as an iCalendar feed
+pub fn from_state(state: &Value) -> String {
+    let now = Utc::now();
+    let stamp = ics_time(now);
+
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(
+        &mut ics,
+        &format!(
+            "PRODID:-//{}//{}//EN",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ),
+    );
+    push_line(&mut ics, "X-WR-CALNAME:Tokopedia campaigns");
+
+    let products = state["products"].as_object().into_iter().flatten();
+    for (hash, product) in products {
+        let values = &product["values"];
+        let Some(end) = values["campaign-ends"]
+            .as_str()
+            .and_then(|end| DateTime::parse_from_rfc3339(end).ok())
+            .map(|end| end.with_timezone(&Utc))
+            .filter(|end| *end > now)
+        else {
+            continue;
+        };
+        let name = values["name"].as_str().unwrap_or(hash);
+        let campaign = values["campaign"]
+            .as_str()
+            .filter(|c| *c != "None")
+            .unwrap_or("Campaign");
+
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(
+            &mut ics,
+            &format!("UID:tkpd-{hash}-{}@ha-tkpd", end.timestamp()),
+        );
+        push_line(&mut ics, &format!("DTSTAMP:{stamp}"));
+        push_line(&mut ics, &format!("DTSTART:{}", ics_time(end)));
+        push_line(&mut ics, &format!("DTEND:{}", ics_time(end)));
+        push_line(
+            &mut ics,
+            &format!("SUMMARY:{}", escape(&format!("{campaign} ends - {name}"))),
+        );
+        if let Some(price) = values["price"].as_str() {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape(&format!("Price: Rp. {price}"))),
+            );
+        }
+        let group = values["attributes"]
+            .as_str()
+            .and_then(|attributes| serde_json::from_str::<Value>(attributes).ok())
+            .and_then(|attributes| attributes["group"].as_str().map(str::to_string));
+        if let Some(group) = group {
+            push_line(&mut ics, &format!("CATEGORIES:{}", escape(&group)));
+        }
+        if let Some(url) = product["configuration_url"].as_str() {
+            push_line(&mut ics, &format!("URL:{url}"));
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Appends a content line, folded at 75 octets as RFC 5545 requires
+fn push_line(ics: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            width = 1;
+        }
+        ics.push(c);
+        width += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
@@ -0,0 +1,1076 @@
+//! Command line flags and the clients they configure.
+//!
+//! Every option is read, in order of precedence, from its flag, a `TKPD_TRACKER_*` environment
+//! variable named after the flag, the `--config` file, and finally its default.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::NaiveDate;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{
+    ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+    ValueHint,
+};
+use clap_complete::Shell;
+use log::{info, warn};
+use reqwest::Client;
+use reqwest::header::{HeaderName, HeaderValue};
+use rumqttc::MqttOptions;
+use tkpd_tracker::TrackerError;
+use tkpd_tracker::ha::{self, ExtraSensor, SensorOverride};
+use tkpd_tracker::tokopedia::{self, LayoutOptions, Tokopedia};
+
+use crate::sink::Sink;
+
+/// Tracks Tokopedia item prices via Home Assistant
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, subcommand_negates_reqs(true))]
+#[allow(clippy::struct_excessive_bools)] // CLI flags
+pub struct Args {
+    #[command(subcommand)]
+    pub action: Option<Action>,
+
+    /// The Tokopedia URL for a price to be tracked
+    #[arg(
+        value_hint(ValueHint::Url),
+        env("TKPD_TRACKER_URL"),
+        hide_env(true),
+        required_unless_present_any([
+            "export_state",
+            "import_state",
+            "export_ics",
+            "watch",
+            "config"
+        ])
+    )]
+    pub url: Option<String>,
+
+    /// MQTT Broker username if required
+    #[arg(long("username"), short('u'), value_hint(ValueHint::Username))]
+    pub mqtt_username: Option<String>,
+    /// MQTT Broker password if required
+    #[arg(long("password"), short('p'))]
+    pub mqtt_password: Option<String>,
+    /// Reads the MQTT Broker password from FILE, e.g. a Docker secret, keeping it out of `ps`
+    #[arg(
+        long("password-file"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath),
+        conflicts_with("mqtt_password")
+    )]
+    pub mqtt_password_file: Option<PathBuf>,
+
+    /// MQTT Broker host or IP
+    #[arg(
+        long("server"),
+        short('s'),
+        value_hint(ValueHint::Hostname),
+        default_value = "localhost"
+    )]
+    pub mqtt_server: String,
+    /// MQTT Broker port
+    #[arg(long("port"), short('x'), default_value_t = 1883)]
+    pub mqtt_port: u16,
+    /// Unacknowledged MQTT publishes allowed in flight at once
+    #[arg(
+        long("max-inflight"),
+        value_name("COUNT"),
+        value_parser(clap::value_parser!(u16).range(1..)),
+        default_value_t = 100
+    )]
+    pub mqtt_max_inflight: u16,
+    /// Gives up at the first MQTT connection error instead of waiting for a reconnect
+    #[arg(long("fail-fast"))]
+    pub fail_fast: bool,
+    /// Only warns when triggers & diagnostics of a scrape fail to publish after its states
+    #[arg(long("lenient"), conflicts_with("fail_fast"))]
+    pub lenient: bool,
+
+    /// HA MQTT autodiscover topic
+    #[arg(long("topic"), short('t'), default_value = "homeassistant")]
+    pub ha_mqtt_discovery_topic: String,
+
+    /// When set, deletes existing data & connection from HA
+    #[arg(long("delete"), short('d'))]
+    pub unretain: bool,
+    /// Prints what tracking would publish without connecting to the broker. With --delete, lists
+    /// the topics it would remove without removing them
+    #[arg(
+        long("dry-run"),
+        conflicts_with_all(["diff", "export_state", "import_state", "export_ics", "watch"])
+    )]
+    pub dry_run: bool,
+    /// Prints the discovery configs & states tracking would retain, one `TOPIC PAYLOAD` line each,
+    /// without connecting to the broker
+    #[arg(
+        long("print-discovery"),
+        conflicts_with_all(["unretain", "dry_run", "diff", "export_state", "import_state", "export_ics", "watch"])
+    )]
+    pub print_discovery: bool,
+    /// Deletes without asking for confirmation
+    #[arg(long("yes"), short('y'), requires("unretain"))]
+    pub yes: bool,
+
+    /// Prints changes since the last publish without publishing
+    #[arg(long("diff"), conflicts_with("unretain"))]
+    pub diff: bool,
+
+    /// Deletes the product from HA instead of tracking it once DATE (YYYY-MM-DD, UTC) has passed
+    #[arg(long("track-until"), value_name("DATE"))]
+    pub track_until: Option<NaiveDate>,
+    /// Skips products updated less than MINS minutes ago, for a schedule shared by products that
+    /// need scraping less often
+    #[arg(long("min-interval"), value_name("MINS"))]
+    pub min_interval: Option<u32>,
+
+    /// Where the scraped product goes, only mqtt publishes to Home Assistant
+    #[arg(long("sink"), value_enum, default_value_t = Sink::Mqtt)]
+    pub sink: Sink,
+    /// File the file sink appends to
+    #[arg(
+        long("output"),
+        short('o'),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath),
+        required_if_eq("sink", "file")
+    )]
+    pub output: Option<PathBuf>,
+    /// What a tracking run prints to stdout: the summary, the scrape as JSON or nothing
+    #[arg(long("output-format"), value_enum, default_value_t = OutputFormat::Pretty)]
+    pub output_format: OutputFormat,
+
+    /// Writes the raw product data to FILE, or stdout when no FILE is given
+    #[arg(
+        long("dump-raw"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("-")
+    )]
+    pub dump_raw: Option<PathBuf>,
+
+    /// Appends this run's price & stock to a CSV price history
+    #[arg(
+        long("history-csv"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath)
+    )]
+    pub history_csv: Option<PathBuf>,
+    /// Signs each --history-csv row with the key in FILE, so the history can be checked for edits
+    #[arg(
+        long("history-key"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath)
+    )]
+    pub history_key: Option<PathBuf>,
+    /// Shares each scraped price, without anything identifying you, on TOPIC. --watch collects the
+    /// prices others share there
+    #[arg(long("share"), value_name("TOPIC"), value_parser(parse_share_topic))]
+    pub share: Option<String>,
+
+    /// Publishes a product data field as an extra sensor
+    #[arg(
+        long("extra-sensor"),
+        value_name("NAME=PATH"),
+        value_parser(ExtraSensor::parse)
+    )]
+    pub extra_sensors: Vec<ExtraSensor>,
+    /// Overrides a discovery field of a sensor, an empty VALUE removes it
+    #[arg(
+        long("sensor-option"),
+        value_name("KEY.FIELD=VALUE"),
+        value_parser(SensorOverride::parse)
+    )]
+    pub sensor_overrides: Vec<SensorOverride>,
+
+    /// Rounds the published price to the nearest STEP
+    #[arg(long("price-rounding"), value_name("STEP"), value_parser(clap::value_parser!(i64).range(1..)))]
+    pub price_rounding: Option<i64>,
+    /// Also publishes the unrounded price as a separate sensor
+    #[arg(long("publish-raw-price"), requires("price_rounding"))]
+    pub publish_raw_price: bool,
+    /// Also publishes the share of campaign stock left as a percentage sensor
+    #[arg(long("publish-stock-percent"))]
+    pub publish_stock_percent: bool,
+
+    /// PDP layout to request from Tokopedia, for when a new layout breaks the default one
+    #[arg(long("layout-id"), value_name("ID"))]
+    pub layout_id: Option<String>,
+    /// PDP API version to request from Tokopedia
+    #[arg(long("api-version"), value_name("VERSION"), default_value_t = 1.0)]
+    pub api_version: f64,
+
+    /// Requests as the same device every run so promos price consistently, with an ID generated
+    /// from the instance ID unless one is given
+    #[arg(
+        long("device-id"),
+        value_name("ID"),
+        num_args(0..=1),
+        require_equals(true),
+        default_missing_value("auto")
+    )]
+    pub device_id: Option<String>,
+
+    /// Sends an extra HTTP header with Tokopedia requests, replacing the default one of that NAME
+    #[arg(long("header"), value_name("NAME:VALUE"), value_parser(parse_header))]
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// HTTP version used for Tokopedia requests
+    #[arg(long("http-version"), value_enum, default_value_t = HttpVersion::Auto)]
+    pub http_version: HttpVersion,
+    /// Seconds an idle HTTP connection is kept around for reuse
+    #[arg(long("pool-idle-timeout"), value_name("SECS"), default_value_t = 90)]
+    pub pool_idle_timeout: u64,
+    /// Maximum idle HTTP connections kept per host
+    #[arg(long("pool-max-idle"), default_value_t = usize::MAX, hide_default_value(true))]
+    pub pool_max_idle: usize,
+    /// Interval of TCP keepalive probes on HTTP connections
+    #[arg(long("tcp-keepalive"), value_name("SECS"))]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Flags price changes larger than PERCENT as anomalies
+    #[arg(long("anomaly-threshold"), value_name("PERCENT"))]
+    pub anomaly_threshold: Option<f64>,
+    /// Flags prices below this amount as anomalies
+    #[arg(long("price-floor"), value_name("IDR"))]
+    pub price_floor: Option<i64>,
+    /// Holds back anomalous prices until the next scrape confirms them
+    #[arg(long("hold-anomalies"))]
+    pub hold_anomalies: bool,
+
+    /// Pauses requests to Tokopedia after COUNT scrapes in a row failed on its side
+    #[arg(long("breaker-failures"), value_name("COUNT"), value_parser(clap::value_parser!(u32).range(1..)))]
+    pub breaker_failures: Option<u32>,
+    /// How long requests stay paused once --breaker-failures is reached
+    #[arg(long("breaker-cooldown"), value_name("MINS"), default_value_t = 60)]
+    pub breaker_cooldown: u32,
+
+    /// Only fires the price dropped trigger for drops of at least PERCENT
+    #[arg(long("drop-threshold"), value_name("PERCENT"))]
+    pub drop_threshold: Option<f64>,
+    /// Re-checks price drops larger than PERCENT before publishing them
+    #[arg(long("confirm-drop"), value_name("PERCENT"))]
+    pub confirm_drop: Option<f64>,
+    /// Seconds to wait before re-checking a large price drop
+    #[arg(long("confirm-delay"), value_name("SECS"), default_value_t = 30)]
+    pub confirm_delay: u64,
+
+    /// Strips emoji & promo spam from the product name used in HA
+    #[arg(long("clean-name"))]
+    pub clean_name: bool,
+    /// Names the product in HA instead of using its listing name
+    #[arg(long("name"), value_name("NAME"))]
+    pub name: Option<String>,
+    /// Names the product's entities `<platform>.tkpd_<ID>_<key>` instead of after its name. Only
+    /// entities HA hasn't seen yet take it
+    #[arg(long("object-id"), value_name("ID"), value_parser(parse_object_id))]
+    pub object_id: Option<String>,
+    /// Truncates the product name used in HA to this many characters
+    #[arg(long("name-max-length"), value_name("CHARS"))]
+    pub name_max_length: Option<usize>,
+    /// Groups the product with others in HA, suggesting an area of this NAME for its device
+    #[arg(long("group"), value_name("NAME"))]
+    pub group: Option<String>,
+
+    /// Writes every tracked product's retained topics to FILE
+    #[arg(long("export-state"), value_name("FILE"), value_hint(ValueHint::FilePath), conflicts_with_all(["url", "import_state"]))]
+    pub export_state: Option<PathBuf>,
+    /// Re-publishes retained topics from a file written by --export-state
+    #[arg(
+        long("import-state"),
+        visible_alias("restore"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath),
+        conflicts_with("url")
+    )]
+    pub import_state: Option<PathBuf>,
+    /// Writes upcoming campaign end times of every tracked product to an iCalendar FILE
+    #[arg(
+        long("export-ics"),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath),
+        conflicts_with_all(["url", "import_state"])
+    )]
+    pub export_ics: Option<PathBuf>,
+    /// Prints the values of every tracked product as they are published, until interrupted
+    #[arg(
+        long("watch"),
+        conflicts_with_all(["url", "export_state", "import_state", "export_ics"])
+    )]
+    pub watch: bool,
+
+    /// Disables colors in the summary & logs
+    #[arg(long("no-color"))]
+    pub no_color: bool,
+    /// Only logs errors and skips the summary
+    #[arg(long("quiet"), short('q'), conflicts_with("verbose"))]
+    pub quiet: bool,
+    /// Logs more details, repeat for even more
+    #[arg(long("verbose"), short('v'), action(ArgAction::Count))]
+    pub verbose: u8,
+    /// Writes log lines as text, or as JSON objects for log collectors
+    #[arg(long("log-format"), value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Appends the logs to FILE instead of writing them to stderr
+    #[arg(long("log-file"), value_name("FILE"), value_hint(ValueHint::FilePath))]
+    pub log_file: Option<PathBuf>,
+
+    /// Name of this tracker instance, defaults to the hostname
+    #[arg(long("instance-id"))]
+    pub instance_id: Option<String>,
+
+    /// Reads options not given as flags or environment variables from a TOML FILE
+    #[arg(
+        long("config"),
+        short('c'),
+        value_name("FILE"),
+        value_hint(ValueHint::FilePath)
+    )]
+    pub config: Option<PathBuf>,
+    /// Loads variables that aren't set yet from this FILE instead of ./.env
+    #[arg(long("env-file"), value_name("FILE"), value_hint(ValueHint::FilePath))]
+    pub env_file: Option<PathBuf>,
+}
+
+/// What to do, a bare URL still tracks it without the `track` subcommand
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Scrapes a product & publishes it to Home Assistant
+    Track {
+        /// The Tokopedia URL for a price to be tracked
+        #[arg(value_hint(ValueHint::Url))]
+        url: String,
+    },
+    /// Prints changes of a product since its last publish without publishing, the same as --diff
+    Diff {
+        /// The Tokopedia URL of the product
+        #[arg(value_hint(ValueHint::Url))]
+        url: String,
+    },
+    /// Deletes a product's device & data from Home Assistant, the same as --delete
+    Delete {
+        /// The Tokopedia URL of the product, or its HA object hash
+        #[arg(
+            required_unless_present("all_from_config"),
+            conflicts_with("all_from_config")
+        )]
+        product: Option<String>,
+        /// Deletes every product of the --config file's [[products]], one after the other
+        #[arg(long("all-from-config"))]
+        all_from_config: bool,
+        /// Lists the topics that would be removed without removing them
+        #[arg(long("dry-run"))]
+        dry_run: bool,
+        /// Deletes without asking for confirmation
+        #[arg(long("yes"), short('y'))]
+        yes: bool,
+    },
+    /// Lists every product tracked on the broker
+    List,
+    /// Shows a live table of every product tracked on the broker, to refresh or delete them from
+    Tui,
+    /// Prints the values of every tracked product as they are published, the same as --watch
+    Watch,
+    /// Compares the --config file's products with those on the broker, to find orphans of either
+    Audit {
+        /// Deletes the products on the broker that the config file doesn't list
+        #[arg(long("fix"))]
+        fix: bool,
+        /// Deletes without asking for confirmation
+        #[arg(long("yes"), short('y'), requires("fix"))]
+        yes: bool,
+    },
+    /// Adds the product URLs of a text file, one per line, to the config file or tracks them
+    Import {
+        /// Text file of URLs, blank lines & lines starting with # are skipped
+        #[arg(value_hint(ValueHint::FilePath))]
+        file: PathBuf,
+        /// Tracks the products now instead of adding them to the --config file
+        #[arg(long("track"))]
+        track: bool,
+    },
+    /// Adds the products tracked on the broker that the --config file doesn't list yet to it
+    Adopt,
+    /// Backs up or restores the retained topics of every tracked product
+    State {
+        #[command(subcommand)]
+        action: StateAction,
+    },
+    /// Fetches a product & prints what would be published, or checks the config file, without publishing
+    Check {
+        /// The Tokopedia URL of the product. Without one, checks the products & broker of the
+        /// --config file instead
+        #[arg(value_hint(ValueHint::Url))]
+        url: Option<String>,
+    },
+    /// Prints the completion script of a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Prints the man page in roff
+    Man,
+    /// Checks every row of a signed --history-csv file against the --history-key
+    VerifyHistory {
+        #[arg(value_hint(ValueHint::FilePath))]
+        file: PathBuf,
+    },
+    /// Rolls --history-csv rows older than some days into daily min/avg/max rows of another file
+    CompactHistory {
+        #[arg(value_hint(ValueHint::FilePath))]
+        file: PathBuf,
+        /// Keeps the rows of the last DAYS days as they are
+        #[arg(long("keep-days"), value_name("DAYS"), default_value_t = 90)]
+        keep_days: u32,
+        /// CSV the daily rows are appended to, FILE with a .daily.csv extension by default
+        #[arg(long("daily"), value_name("FILE"), value_hint(ValueHint::FilePath))]
+        daily: Option<PathBuf>,
+    },
+    /// Asks for the broker & products to track, checks them and writes a config file
+    Init {
+        /// Where to write the config file
+        #[arg(value_hint(ValueHint::FilePath), default_value = "tracker.toml")]
+        path: PathBuf,
+    },
+}
+
+/// What `state` does with the tracker state
+#[derive(Subcommand, Debug)]
+pub enum StateAction {
+    /// Writes every tracked product's retained topics to FILE, the same as --export-state
+    Export {
+        #[arg(value_hint(ValueHint::FilePath))]
+        file: PathBuf,
+    },
+    /// Re-publishes retained topics from a file written by `state export`, the same as --import-state
+    Import {
+        #[arg(value_hint(ValueHint::FilePath))]
+        file: PathBuf,
+    },
+}
+
+const ENV_PREFIX: &str = "TKPD_TRACKER_";
+
+/// The flags, with every option that takes a value or is a switch also read from the environment
+fn command() -> Command {
+    Args::command().mut_args(|arg| {
+        let env = match (arg.get_long(), arg.get_action()) {
+            (Some(long), ArgAction::Set | ArgAction::Append | ArgAction::SetTrue) => {
+                format!("{ENV_PREFIX}{}", long.to_uppercase().replace('-', "_"))
+            }
+            _ => return arg,
+        };
+        arg.env(env).hide_env(true)
+    })
+}
+
+/// Writes the completion script of a shell to stdout
+pub fn print_completions(shell: Shell) {
+    let mut command = command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+}
+
+/// Writes the man page to stdout, with the environment variable of every option
+pub fn print_man() -> Result<(), TrackerError> {
+    let command = command().mut_args(|arg| arg.hide_env(false));
+    clap_mangen::Man::new(command)
+        .render(&mut std::io::stdout())
+        .map_err(TrackerError::io("Unable to write the man page"))
+}
+
+/// Parses the flags & environment, then fills in what neither set from the config file. The .env
+/// file is loaded into the environment first, so this has to run before other threads start. Gives one
+/// set of options per `[[products]]` entry when the config file has them and no URL or mode was
+/// given, otherwise a single one. Exits with a usage error like clap does when any is invalid.
+pub fn parse() -> Vec<Args> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let mut command = command();
+    if let Err(e) = load_env_file(&argv) {
+        command.error(ErrorKind::Io, e).exit();
+    }
+    let matches = command.clone().get_matches_from(&argv);
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        let deletes_products = matches
+            .subcommand_matches("delete")
+            .is_some_and(|delete| delete.get_flag("all_from_config"));
+        if deletes_products {
+            command
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "delete --all-from-config reads the products from the --config file",
+                )
+                .exit();
+        }
+        return vec![into_args(&matches)];
+    };
+
+    let runs = config_runs(&command, &matches, path)
+        .unwrap_or_else(|e| command.error(ErrorKind::InvalidValue, e).exit());
+    runs.into_iter()
+        .map(|(config_args, url)| {
+            let run_args = into_args(&command.clone().get_matches_from(product_argv(
+                &argv,
+                config_args,
+                url,
+            )));
+            let has_mode = run_args.export_state.is_some()
+                || run_args.import_state.is_some()
+                || run_args.export_ics.is_some()
+                || run_args.watch;
+            if run_args.url.is_none() && run_args.action.is_none() && !has_mode {
+                command
+                    .error(
+                        ErrorKind::MissingRequiredArgument,
+                        "A URL is required, as an argument or as [[products]] in the config file",
+                    )
+                    .exit();
+            }
+            run_args
+        })
+        .collect()
+}
+
+/// The command line of a config file run. Config entries go before the flags, which they never
+/// repeat, and the product URL last
+fn product_argv(
+    argv: &[OsString],
+    config_args: Vec<OsString>,
+    url: Option<OsString>,
+) -> Vec<OsString> {
+    // A product run of `delete --all-from-config` deletes the one product it's given instead
+    let for_product = url.is_some();
+    argv.iter()
+        .take(1)
+        .cloned()
+        .chain(config_args)
+        .chain(
+            argv.iter()
+                .skip(1)
+                .filter(|arg| !(for_product && *arg == "--all-from-config"))
+                .cloned(),
+        )
+        .chain(url)
+        .collect()
+}
+
+/// The URL of a config file product, with its options or why they are invalid
+pub type CheckedProduct = (String, Result<Args, String>);
+
+/// Every `[[products]]` entry of the config file with its options parsed the way its run would
+/// parse them, for `check`. Gives the URL of each product with its options, or why they are invalid
+pub fn check_config(path: &Path) -> Result<Vec<CheckedProduct>, String> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let command = command();
+    let matches = command
+        .clone()
+        .try_get_matches_from(&argv)
+        .map_err(|e| clap_message(&e))?;
+    let (config, products) = read_config(path)?;
+    let Some(products) = products else {
+        return Err(format!("No [[products]] in config file {}", path.display()));
+    };
+    let products = product_entries(&products, path)?;
+
+    let runs = product_runs(&command, &matches, &config, products, path);
+    Ok(products
+        .iter()
+        .zip(runs)
+        .map(|(product, run)| {
+            let url = product
+                .get("url")
+                .and_then(toml::Value::as_str)
+                .unwrap_or("(no url)")
+                .to_string();
+            let run_args = run.and_then(|(config_args, url)| {
+                command
+                    .clone()
+                    .try_get_matches_from(product_argv(&argv, config_args, url))
+                    .and_then(|matches| Args::from_arg_matches(&matches))
+                    .map_err(|e| clap_message(&e))
+            });
+            (url, run_args)
+        })
+        .collect())
+}
+
+/// The first line of a clap error, without its `error: ` prefix
+fn clap_message(e: &clap::Error) -> String {
+    let message = e.to_string();
+    let line = message.lines().next().unwrap_or_default();
+    line.strip_prefix("error: ").unwrap_or(line).to_string()
+}
+
+fn into_args(matches: &ArgMatches) -> Args {
+    let mut args = Args::from_arg_matches(matches).unwrap_or_else(|e| e.exit());
+
+    // Subcommands standing in for the flags they were added next to
+    match args.action.take() {
+        Some(Action::Track { url }) => args.url = Some(url),
+        Some(Action::Diff { url }) => {
+            args.url = Some(url);
+            args.diff = true;
+        }
+        // `--all-from-config` is read from the matches, each product run is given its URL instead
+        Some(Action::Delete {
+            product,
+            all_from_config: _,
+            dry_run,
+            yes,
+        }) => {
+            args.url = product;
+            args.unretain = true;
+            args.dry_run |= dry_run;
+            args.yes |= yes;
+        }
+        Some(Action::Watch) => args.watch = true,
+        Some(Action::State {
+            action: StateAction::Export { file },
+        }) => args.export_state = Some(file),
+        Some(Action::State {
+            action: StateAction::Import { file },
+        }) => args.import_state = Some(file),
+        action => args.action = action,
+    }
+    args
+}
+
+/// The `--env-file` path, read ahead of clap since the file sets variables clap reads
+fn env_file_flag(argv: &[OsString]) -> Option<PathBuf> {
+    let mut argv = argv.iter().skip(1).take_while(|arg| *arg != "--");
+    while let Some(arg) = argv.next() {
+        if arg == "--env-file" {
+            return argv.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--env-file=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os(format!("{ENV_PREFIX}ENV_FILE")).map(PathBuf::from)
+}
+
+/// Sets the `KEY=VALUE` lines of the .env file as environment variables, leaving variables that are
+/// already set alone. A missing ./.env is fine, a missing `--env-file` isn't
+fn load_env_file(argv: &[OsString]) -> Result<(), String> {
+    let explicit = env_file_flag(argv);
+    let path = explicit.clone().unwrap_or_else(|| PathBuf::from(".env"));
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if explicit.is_none() && e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Unable to read env file {} - {e}", path.display())),
+    };
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty() && !key.contains('\0'))
+        else {
+            return Err(format!(
+                "Line {} of env file {} isn't KEY=VALUE",
+                number + 1,
+                path.display()
+            ));
+        };
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        if std::env::var_os(key).is_none() {
+            // SAFETY: parse() runs before the async runtime or anything else starts a thread
+            unsafe { std::env::set_var(key, value) };
+        }
+    }
+    Ok(())
+}
+
+/// Whether a flag or variable set the option
+fn is_given(matches: &ArgMatches, id: &str) -> bool {
+    matches
+        .value_source(id)
+        .is_some_and(|source| source != ValueSource::DefaultValue)
+}
+
+/// Config file flags of a run, along with the URL of the product it tracks
+type ConfigRun = (Vec<OsString>, Option<OsString>);
+
+/// The config file flags of every run
+fn config_runs(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &Path,
+) -> Result<Vec<ConfigRun>, String> {
+    let (config, products) = read_config(path)?;
+
+    let deletes_products = matches
+        .subcommand_matches("delete")
+        .is_some_and(|delete| delete.get_flag("all_from_config"));
+    let tracks_products = (matches.subcommand().is_none() || deletes_products)
+        && ["url", "export_state", "import_state", "export_ics", "watch"]
+            .iter()
+            .all(|id| !is_given(matches, id));
+    let Some(products) = products.filter(|_| tracks_products) else {
+        return Ok(vec![(config_args(command, matches, &config, path)?, None)]);
+    };
+    let products = product_entries(&products, path)?;
+    product_runs(command, matches, &config, products, path)
+        .into_iter()
+        .collect()
+}
+
+/// The config file's table, without its `products` which are given apart
+fn read_config(path: &Path) -> Result<(toml::Table, Option<toml::Value>), String> {
+    let config = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read config file {} - {e}", path.display()))?;
+    let mut config: toml::Table = toml::from_str(&config)
+        .map_err(|e| format!("Invalid config file {} - {e}", path.display()))?;
+    let products = config.remove("products");
+    Ok((config, products))
+}
+
+fn product_entries<'a>(
+    products: &'a toml::Value,
+    path: &Path,
+) -> Result<&'a [toml::Value], String> {
+    let Some(products) = products.as_array() else {
+        return Err(format!(
+            "\"products\" must be an array of tables in config file {}",
+            path.display()
+        ));
+    };
+    if products.is_empty() {
+        return Err(format!(
+            "\"products\" is empty in config file {}",
+            path.display()
+        ));
+    }
+    Ok(products)
+}
+
+/// The config file flags of the run of each product, or why they can't be used
+fn product_runs(
+    command: &Command,
+    matches: &ArgMatches,
+    config: &toml::Table,
+    products: &[toml::Value],
+    path: &Path,
+) -> Vec<Result<ConfigRun, String>> {
+    products
+        .iter()
+        .map(|product| {
+            let Some(mut product) = product.as_table().cloned() else {
+                return Err(format!(
+                    "\"products\" must be an array of tables in config file {}",
+                    path.display()
+                ));
+            };
+            let Some(toml::Value::String(url)) = product.remove("url") else {
+                return Err(format!(
+                    "A product without a \"url\" in config file {}",
+                    path.display()
+                ));
+            };
+            // Options of the product take over those shared by every product
+            let mut options = config.clone();
+            options.extend(product);
+            Ok((
+                config_args(command, matches, &options, path)?,
+                Some(url.into()),
+            ))
+        })
+        .collect()
+}
+
+/// The `url` of every `[[products]]` entry of a config file, with the `track-until` date it's given
+pub fn product_urls(path: &Path) -> Result<Vec<(String, Option<NaiveDate>)>, TrackerError> {
+    let config = std::fs::read_to_string(path).map_err(TrackerError::io(format!(
+        "Unable to read config file {}",
+        path.display()
+    )))?;
+    let config: toml::Table = toml::from_str(&config).map_err(|e| {
+        TrackerError::Usage(format!("Invalid config file {} - {e}", path.display()))
+    })?;
+    let shared_until = track_until(config.get("track-until"));
+    Ok(config
+        .get("products")
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|product| {
+            let url = product.get("url")?.as_str()?.to_string();
+            Some((
+                url,
+                track_until(product.get("track-until")).or(shared_until),
+            ))
+        })
+        .collect())
+}
+
+/// A `track-until` config value as a date, leaving invalid ones to the run that reads them
+fn track_until(value: Option<&toml::Value>) -> Option<NaiveDate> {
+    match value? {
+        toml::Value::String(date) => date.parse().ok(),
+        toml::Value::Datetime(date) => date.to_string().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Replaces the `${env:NAME}` and `${file:PATH}` references of a config string with the variable
+/// or the file's contents, without its trailing line break, so secrets can stay out of the file
+fn interpolate(value: &str, path: &Path) -> Result<String, String> {
+    let mut interpolated = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        interpolated.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!(
+                "Unclosed ${{ in {value:?} of config file {}",
+                path.display()
+            ));
+        };
+        let reference = &rest[start + 2..start + end];
+        match reference.split_once(':') {
+            Some(("env", name)) => {
+                let variable = std::env::var(name).map_err(|e| {
+                    format!("{e} - ${{env:{name}}} of config file {}", path.display())
+                })?;
+                interpolated.push_str(&variable);
+            }
+            Some(("file", file)) => {
+                let contents = std::fs::read_to_string(file).map_err(|e| {
+                    format!(
+                        "Unable to read {file} for config file {} - {e}",
+                        path.display()
+                    )
+                })?;
+                interpolated.push_str(contents.trim_end_matches(['\n', '\r']));
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown reference ${{{reference}}} in config file {}, expected ${{env:NAME}} \
+                     or ${{file:PATH}}",
+                    path.display()
+                ));
+            }
+        }
+        rest = &rest[start + end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+/// Flags standing in for the config file entries that neither a flag nor a variable overrides
+fn config_args(
+    command: &Command,
+    matches: &ArgMatches,
+    config: &toml::Table,
+    path: &Path,
+) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (key, value) in config {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+            .filter(|_| key != "config" && key != "env-file")
+            .ok_or_else(|| format!("Unknown option {key:?} in config file {}", path.display()))?;
+        if is_given(matches, arg.get_id().as_str()) {
+            continue;
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            match (value, arg.get_action()) {
+                (toml::Value::Boolean(true), ArgAction::SetTrue) => args.push(format!("--{key}")),
+                (toml::Value::Boolean(false), ArgAction::SetTrue) => {}
+                (toml::Value::Integer(count), ArgAction::Count) => {
+                    for _ in 0..*count {
+                        args.push(format!("--{key}"));
+                    }
+                }
+                (toml::Value::String(value), _) => {
+                    let value = interpolate(value, path)?;
+                    args.push(format!("--{key}={value}"));
+                }
+                (toml::Value::Integer(value), _) => args.push(format!("--{key}={value}")),
+                (toml::Value::Float(value), _) => args.push(format!("--{key}={value}")),
+                (toml::Value::Datetime(value), _) => args.push(format!("--{key}={value}")),
+                _ => {
+                    return Err(format!(
+                        "Unsupported value for {key:?} in config file {}",
+                        path.display()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(args.into_iter().map(OsString::from).collect())
+}
+
+impl Args {
+    /// HTTP client for Tokopedia requests, with the connection pool tuned by the flags
+    pub fn http_client(&self) -> Result<Client, TrackerError> {
+        let builder = tokopedia::client_builder()
+            .pool_idle_timeout(Duration::from_secs(self.pool_idle_timeout))
+            .pool_max_idle_per_host(self.pool_max_idle)
+            .tcp_keepalive(self.tcp_keepalive.map(Duration::from_secs));
+        let builder = match self.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            #[cfg(feature = "http2")]
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+            #[cfg(not(feature = "http2"))]
+            HttpVersion::Http2 => {
+                return Err(TrackerError::Usage(
+                    "--http-version 2 needs the http2 feature, which this build was made without"
+                        .to_string(),
+                ));
+            }
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Tokopedia, requesting the layout & device with the headers the flags ask for
+    pub fn price_source(&self) -> Result<Tokopedia, TrackerError> {
+        let device_id = self.device_id.as_ref().map(|id| {
+            if id == "auto" {
+                let instance_id = self.instance_id.clone().unwrap_or_else(default_instance_id);
+                format!("ha-tkpd-{}", ha::short_hash(&[instance_id]))
+            } else {
+                id.clone()
+            }
+        });
+        Ok(Tokopedia::new(self.http_client()?)
+            .with_layout(LayoutOptions {
+                layout_id: self.layout_id.clone(),
+                api_version: self.api_version,
+                device_id,
+            })
+            .with_headers(self.headers.iter().cloned().collect()))
+    }
+
+    /// Broker connection options from the MQTT flags
+    pub fn mqtt_options(&self) -> Result<MqttOptions, TrackerError> {
+        let password = match &self.mqtt_password_file {
+            Some(path) => Some(read_secret(path)?),
+            None => self.mqtt_password.clone(),
+        };
+        if password.is_some() && self.mqtt_username.is_none() {
+            return Err(TrackerError::Usage(
+                "MQTT Broker password is provided without any username. Aborting...".to_string(),
+            ));
+        }
+        if self.mqtt_username.is_some() && password.is_none() {
+            warn!("MQTT Broker username is provided without password. Continuing...");
+        }
+
+        let mut mqtt_opts = MqttOptions::new(
+            format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+            self.mqtt_server.clone(),
+            self.mqtt_port,
+        );
+
+        if let Some(username) = &self.mqtt_username {
+            info!(target: "mqtt", "Using provided credentials");
+            mqtt_opts.set_credentials(username.clone(), password.unwrap_or_default());
+        }
+        mqtt_opts.set_keep_alive(Duration::from_secs(10));
+        mqtt_opts.set_inflight(self.mqtt_max_inflight);
+        Ok(mqtt_opts)
+    }
+}
+
+/// Reads a secret kept in a file, without the line break editors & `echo` leave at its end
+fn read_secret(path: &Path) -> Result<String, TrackerError> {
+    let secret = std::fs::read_to_string(path).map_err(TrackerError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    Ok(secret.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Checks an object ID is usable in an entity ID, lowercase letters, digits & underscores
+fn parse_object_id(s: &str) -> Result<String, String> {
+    if s.is_empty()
+        || !s
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err("expected lowercase letters, digits & underscores".to_string());
+    }
+    Ok(s.to_string())
+}
+
+/// Checks a `--share` topic stays out of `tkpdprice/`, where observations would be read back as
+/// the retained state of the products
+fn parse_share_topic(s: &str) -> Result<String, String> {
+    if s.is_empty() {
+        return Err("topic is empty".to_string());
+    }
+    if s == "tkpdprice" || s.starts_with("tkpdprice/") {
+        return Err(
+            "tkpdprice/ holds the state of tracked products, pick another topic".to_string(),
+        );
+    }
+    Ok(s.to_string())
+}
+
+/// Reads a `NAME:VALUE` header
+fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s.split_once(':').ok_or("expected NAME:VALUE")?;
+    let name =
+        HeaderName::try_from(name.trim()).map_err(|e| format!("invalid header name - {e}"))?;
+    let value =
+        HeaderValue::from_str(value.trim()).map_err(|e| format!("invalid header value - {e}"))?;
+    Ok((name, value))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // `[time level target hash] message`, colored on a terminal
+    Text,
+    // One object per line with the timestamp, level, target, message & product_hash
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    // One JSON object with the scraped product, its previous values and the fired triggers
+    Json,
+    // The summary table
+    Pretty,
+    // Nothing, the logs on stderr are all there is
+    None,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum HttpVersion {
+    // Negotiate with the server, HTTP/2 when available
+    Auto,
+    #[value(name = "1")]
+    Http1,
+    // Assume the server speaks HTTP/2 without negotiating
+    #[value(name = "2")]
+    Http2,
+}
+
+pub fn default_instance_id() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
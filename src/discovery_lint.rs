@@ -0,0 +1,304 @@
+//! Checks generated discovery payloads against the Home Assistant MQTT discovery schema.
+//!
+//! HA only logs a warning when it receives a config it does not understand, so the entity
+//! silently never shows up. Only the options this tool may emit are covered here.
+
+use serde_json::{Map, Value};
+
+/// Options accepted by every MQTT entity platform
+const COMMON_KEYS: &[&str] = &[
+    "availability",
+    "availability_mode",
+    "availability_template",
+    "availability_topic",
+    "default_entity_id",
+    "device",
+    "enabled_by_default",
+    "encoding",
+    "entity_category",
+    "entity_picture",
+    "icon",
+    "json_attributes_template",
+    "json_attributes_topic",
+    "name",
+    "object_id",
+    "origin",
+    "payload_available",
+    "payload_not_available",
+    "platform",
+    "qos",
+    "unique_id",
+];
+
+const SENSOR_KEYS: &[&str] = &[
+    "device_class",
+    "expire_after",
+    "force_update",
+    "last_reset_value_template",
+    "options",
+    "state_class",
+    "state_topic",
+    "suggested_display_precision",
+    "unit_of_measurement",
+    "value_template",
+];
+
+const BINARY_SENSOR_KEYS: &[&str] = &[
+    "device_class",
+    "expire_after",
+    "force_update",
+    "off_delay",
+    "payload_off",
+    "payload_on",
+    "state_topic",
+    "value_template",
+];
+
+const SWITCH_KEYS: &[&str] = &[
+    "command_template",
+    "command_topic",
+    "device_class",
+    "optimistic",
+    "payload_off",
+    "payload_on",
+    "retain",
+    "state_off",
+    "state_on",
+    "state_topic",
+    "value_template",
+];
+
+const TEXT_KEYS: &[&str] = &[
+    "command_template",
+    "command_topic",
+    "max",
+    "min",
+    "mode",
+    "pattern",
+    "retain",
+    "state_topic",
+    "value_template",
+];
+
+const DEVICE_AUTOMATION_KEYS: &[&str] = &[
+    "automation_type",
+    "device",
+    "payload",
+    "platform",
+    "qos",
+    "subtype",
+    "topic",
+    "type",
+    "value_template",
+];
+
+const DEVICE_KEYS: &[&str] = &[
+    "configuration_url",
+    "connections",
+    "hw_version",
+    "identifiers",
+    "manufacturer",
+    "model",
+    "model_id",
+    "name",
+    "serial_number",
+    "suggested_area",
+    "sw_version",
+    "via_device",
+];
+
+const SENSOR_DEVICE_CLASSES: &[&str] = &[
+    "apparent_power",
+    "aqi",
+    "area",
+    "atmospheric_pressure",
+    "battery",
+    "blood_glucose_concentration",
+    "carbon_dioxide",
+    "carbon_monoxide",
+    "conductivity",
+    "current",
+    "data_rate",
+    "data_size",
+    "date",
+    "distance",
+    "duration",
+    "energy",
+    "energy_distance",
+    "energy_storage",
+    "enum",
+    "frequency",
+    "gas",
+    "humidity",
+    "illuminance",
+    "irradiance",
+    "moisture",
+    "monetary",
+    "nitrogen_dioxide",
+    "nitrogen_monoxide",
+    "nitrous_oxide",
+    "ozone",
+    "ph",
+    "pm1",
+    "pm10",
+    "pm25",
+    "power",
+    "power_factor",
+    "precipitation",
+    "precipitation_intensity",
+    "pressure",
+    "reactive_power",
+    "signal_strength",
+    "sound_pressure",
+    "speed",
+    "sulphur_dioxide",
+    "temperature",
+    "timestamp",
+    "volatile_organic_compounds",
+    "volatile_organic_compounds_parts",
+    "voltage",
+    "volume",
+    "volume_flow_rate",
+    "volume_storage",
+    "water",
+    "weight",
+    "wind_direction",
+    "wind_speed",
+];
+
+const BOOLEAN_KEYS: &[&str] = &["enabled_by_default", "force_update", "optimistic", "retain"];
+
+/// Returns every problem found in a discovery payload, or `Ok` when HA should accept it
+///
+/// # Errors
+///
+/// Fails with the list of problems when the payload isn't a valid discovery config.
+pub fn lint_payload(payload: &Value) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+
+    let Some(config) = payload.as_object() else {
+        return Err(vec!["Payload is not a JSON object".to_string()]);
+    };
+
+    let platform = config.get("platform").and_then(Value::as_str);
+    if platform == Some("device_automation") {
+        lint_device_automation(config, &mut problems);
+        return if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        };
+    }
+
+    let (platform_keys, required_keys): (&[&str], &[&str]) = match platform {
+        Some("sensor") => (SENSOR_KEYS, &["state_topic"]),
+        Some("binary_sensor") => (BINARY_SENSOR_KEYS, &["state_topic"]),
+        Some("switch") => (SWITCH_KEYS, &["command_topic"]),
+        Some("text") => (TEXT_KEYS, &["command_topic"]),
+        Some(other) => {
+            return Err(vec![format!("Unsupported platform {other:?}")]);
+        }
+        None => return Err(vec!["Missing \"platform\"".to_string()]),
+    };
+
+    for key in config.keys() {
+        if !COMMON_KEYS.contains(&key.as_str()) && !platform_keys.contains(&key.as_str()) {
+            problems.push(format!("Unknown option {key:?} for platform {platform:?}"));
+        }
+    }
+    for key in required_keys {
+        if !config.contains_key(*key) {
+            problems.push(format!("Missing required option {key:?}"));
+        }
+    }
+
+    for key in BOOLEAN_KEYS {
+        if config.get(*key).is_some_and(|v| !v.is_boolean()) {
+            problems.push(format!("{key:?} must be a boolean"));
+        }
+    }
+    if config
+        .get("suggested_display_precision")
+        .is_some_and(|v| !v.is_u64())
+    {
+        problems.push("\"suggested_display_precision\" must be a positive integer".to_string());
+    }
+    if let Some(category) = config.get("entity_category")
+        && category != "diagnostic"
+        && category != "config"
+    {
+        problems.push(format!(
+            "\"entity_category\" must be \"diagnostic\" or \"config\", got {category}"
+        ));
+    }
+    if let Some(class) = config.get("state_class")
+        && !["measurement", "total", "total_increasing"].contains(&class.as_str().unwrap_or(""))
+    {
+        problems.push(format!("Unknown \"state_class\" {class}"));
+    }
+    if let Some(class) = config.get("device_class")
+        && platform == Some("sensor")
+    {
+        if !SENSOR_DEVICE_CLASSES.contains(&class.as_str().unwrap_or("")) {
+            problems.push(format!("Unknown sensor \"device_class\" {class}"));
+        }
+        if class == "monetary"
+            && config
+                .get("state_class")
+                .is_some_and(|state_class| state_class != "total")
+        {
+            problems.push("Monetary sensors only support the \"total\" state class".to_string());
+        }
+    }
+
+    if let Some(device) = config.get("device") {
+        lint_device(device, &mut problems);
+        if !config.contains_key("unique_id") {
+            problems.push("Entities with a device must have a \"unique_id\"".to_string());
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn lint_device_automation(config: &Map<String, Value>, problems: &mut Vec<String>) {
+    for key in config.keys() {
+        if !DEVICE_AUTOMATION_KEYS.contains(&key.as_str()) {
+            problems.push(format!("Unknown device automation option {key:?}"));
+        }
+    }
+    for key in ["automation_type", "topic", "type", "subtype", "device"] {
+        if !config.contains_key(key) {
+            problems.push(format!("Missing required option {key:?}"));
+        }
+    }
+    if config
+        .get("automation_type")
+        .is_some_and(|t| t != "trigger")
+    {
+        problems.push("\"automation_type\" must be \"trigger\"".to_string());
+    }
+    if let Some(device) = config.get("device") {
+        lint_device(device, problems);
+    }
+}
+
+fn lint_device(device: &Value, problems: &mut Vec<String>) {
+    let Some(device) = device.as_object() else {
+        problems.push("\"device\" is not a JSON object".to_string());
+        return;
+    };
+
+    for key in device.keys() {
+        if !DEVICE_KEYS.contains(&key.as_str()) {
+            problems.push(format!("Unknown device option {key:?}"));
+        }
+    }
+    if !device.contains_key("identifiers") && !device.contains_key("connections") {
+        problems.push("Device needs either \"identifiers\" or \"connections\"".to_string());
+    }
+}
@@ -0,0 +1,91 @@
+//! Errors surfaced by the tracker, each mapped to its own process exit code.
+
+use thiserror::Error;
+
+/// Everything that can make a run fail
+#[derive(Debug, Error)]
+pub enum TrackerError {
+    /// Options that don't work together
+    #[error("{0}")]
+    Usage(String),
+    /// Not a Tokopedia product URL
+    #[error("{0}")]
+    Url(String),
+    #[error("Tokopedia request failed - {0}")]
+    Http(#[from] reqwest::Error),
+    /// Tokopedia answered with a GraphQL error
+    #[error("Unable to fetch product data - {0}")]
+    GraphQl(String),
+    /// The response doesn't have the fields this tool reads
+    #[error("{0} - It seems like Tokopedia changed their API!")]
+    Decode(String),
+    /// Fields this tool reads are gone from the response
+    #[error("Tokopedia's response has no {} - It seems like Tokopedia changed their API!", .0.join(", "))]
+    SchemaDrift(Vec<String>),
+    #[error("Invalid JSON - {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("MQTT request failed - {0}")]
+    Mqtt(#[from] rumqttc::ClientError),
+    /// Queued messages may not have reached the broker
+    #[error("{0}. Messages might not be fully published!")]
+    MqttShutdown(String),
+    #[error("{context} - {source}")]
+    Io {
+        context: String,
+        source: std::io::Error,
+    },
+    /// A state file that can't be imported
+    #[error("Invalid state file - {0}")]
+    State(String),
+    /// A generated discovery config HA would ignore
+    #[error("Generated discovery config for {key:?} is invalid - {}", .problems.join("; "))]
+    Discovery { key: String, problems: Vec<String> },
+    /// The broker refused or never answered the connection
+    #[error("Unable to connect to the MQTT broker - {0}")]
+    MqttUnreachable(String),
+    /// Tokopedia answered, but without a product for the URL
+    #[error("Product not found - {0}")]
+    NotFound(String),
+    /// Rows of a signed price history that were changed since they were written
+    #[error("{0} price history rows don't match their signature")]
+    Tampered(usize),
+}
+
+impl TrackerError {
+    /// Process exit code for this error, usage errors share code 2 with clap
+    #[must_use]
+    pub const fn exit_code(&self) -> u8 {
+        match self {
+            Self::Usage(_) => 2,
+            Self::Url(_) => 3,
+            Self::Http(_) => 4,
+            Self::GraphQl(_) => 5,
+            Self::Decode(_) => 6,
+            Self::Json(_) => 7,
+            Self::Mqtt(_) => 8,
+            Self::MqttShutdown(_) => 9,
+            Self::Io { .. } => 10,
+            Self::State(_) => 11,
+            Self::Discovery { .. } => 12,
+            Self::MqttUnreachable(_) => 13,
+            Self::NotFound(_) => 14,
+            Self::SchemaDrift(_) => 15,
+            Self::Tampered(_) => 16,
+        }
+    }
+
+    /// Whether the broker connection is what failed
+    #[must_use]
+    pub const fn is_mqtt(&self) -> bool {
+        matches!(
+            self,
+            Self::Mqtt(_) | Self::MqttShutdown(_) | Self::MqttUnreachable(_)
+        )
+    }
+
+    /// Wraps a filesystem error with what was being done
+    pub fn io(context: impl Into<String>) -> impl FnOnce(std::io::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Io { context, source }
+    }
+}
@@ -0,0 +1,747 @@
+//! Home Assistant MQTT discovery payloads for a tracked product.
+
+use std::fmt;
+use std::io::Write;
+
+use blake2::Blake2sVar;
+use blake2::digest::VariableOutput;
+use log::warn;
+use serde_json::{Map, Value, json};
+
+/// Retained state of the `--breaker-failures` circuit breaker. One level deep, so it can't be
+/// mistaken for a product's `tkpdprice/<hash>/<key>` topics
+pub const BREAKER_TOPIC: &str = "tkpdprice/breaker";
+
+/// Device triggers, doubles as the payload published on `tkpdprice/<hash>/trigger`
+pub const TRIGGER_KEYS: &[&str] = &[
+    "price-dropped",
+    "restocked",
+    "campaign-started",
+    "price-anomaly",
+    "condition-changed",
+];
+
+/// Short stable ID of a product, used in every topic & unique ID
+///
+/// # Panics
+///
+/// Never, the hasher only fails on invalid output sizes.
+#[must_use]
+pub fn product_hash(shop_domain: &str, product_key: &str) -> String {
+    let mut hasher = Blake2sVar::new(4).unwrap();
+    hasher.write_all(shop_domain.as_bytes()).unwrap();
+    hasher.write_all(product_key.as_bytes()).unwrap();
+    format!("{:x}", HexSlice(&hasher.finalize_boxed()))
+}
+
+/// Short hash of anything, used to tell whether publish settings changed between runs
+///
+/// # Panics
+///
+/// Never, the hasher only fails on invalid output sizes.
+#[must_use]
+pub fn short_hash(parts: &[String]) -> String {
+    let mut hasher = Blake2sVar::new(4).unwrap();
+    for part in parts {
+        hasher.write_all(part.as_bytes()).unwrap();
+    }
+    format!("{:x}", HexSlice(&hasher.finalize_boxed()))
+}
+
+/// The HA device every entity of a product belongs to
+#[must_use]
+pub fn device_info(
+    shop_domain: &str,
+    product_key: &str,
+    product_hash: &str,
+    display_name: &str,
+) -> Value {
+    json!({
+        "manufacturer": shop_domain,
+        "model_id": display_name,
+        "model": "ha-tkpd",
+        "identifiers": format!("tkpdprice-{product_hash}"),
+        "serial_number": format!("{product_hash}"),
+        "sw_version": env!("CARGO_PKG_VERSION"),
+        "configuration_url": format!("https://tokopedia.com/{shop_domain}/{product_key}"),
+        "name": display_name
+    })
+}
+
+/// Discovery configs of the built-in sensors, device triggers, tracking switch & URL text, keyed by
+/// topic suffix
+#[must_use]
+pub fn discovery_configs(product_hash: &str, device: &Value) -> Vec<(String, Value)> {
+    let entities = DiscoveryBuilder::new(product_hash, device);
+    let mut configs = vec![
+        entities.sensor("name", "Name").attributes().build(),
+        entities.sensor("price", "Price").rupiah().build(),
+        entities
+            .sensor("stock", "Stock")
+            .unit("pcs")
+            .display_precision(0)
+            .icon("mdi:numeric")
+            .build(),
+        entities
+            .sensor("stock-delta", "Stock change")
+            .state_class(StateClass::Measurement)
+            .unit("pcs")
+            .display_precision(0)
+            .icon("mdi:delta")
+            .build(),
+        entities
+            .sensor("campaign", "Campaign")
+            .icon("mdi:sale")
+            .build(),
+        entities
+            .sensor("campaign-ends", "Campaign ends")
+            .device_class("timestamp")
+            .build(),
+        entities
+            .sensor("campaign-remaining", "Campaign ends in")
+            .device_class("duration")
+            .unit("min")
+            .display_precision(0)
+            .icon("mdi:timer-sand")
+            .build(),
+        entities
+            .sensor("upcoming-campaign", "Upcoming campaign")
+            .icon("mdi:calendar-star")
+            .build(),
+        entities
+            .sensor("upcoming-campaign-starts", "Upcoming campaign starts")
+            .device_class("timestamp")
+            .build(),
+        entities
+            .sensor("upcoming-campaign-price", "Upcoming campaign price")
+            .rupiah()
+            .build(),
+        entities
+            .sensor("weight", "Weight")
+            .entity_category(EntityCategory::Diagnostic)
+            .device_class("weight")
+            .unit("g")
+            .force_update(false)
+            .attributes()
+            .attributes_template("{{ {'dimensions': value_json.dimensions} | tojson }}")
+            .build(),
+        entities
+            .sensor("lowest-price", "Lowest price")
+            .rupiah()
+            .force_update(false)
+            .icon("mdi:arrow-collapse-down")
+            .build(),
+        entities
+            .sensor("deal-score", "Deal score")
+            .state_class(StateClass::Measurement)
+            .display_precision(0)
+            .icon("mdi:star-circle")
+            .build(),
+        entities
+            .sensor("updated-at", "Last update")
+            .entity_category(EntityCategory::Diagnostic)
+            .device_class("timestamp")
+            .force_update(false)
+            .enabled_by_default(true)
+            .build(),
+        entities
+            .sensor("scraper-version", "Scraper version")
+            .entity_category(EntityCategory::Diagnostic)
+            .force_update(false)
+            .icon("mdi:cogs")
+            .build(),
+        entities
+            .sensor("status", "Run status")
+            .entity_category(EntityCategory::Diagnostic)
+            .force_update(false)
+            .value_template("{{ value_json.state }}")
+            .attributes_from("status")
+            .icon("mdi:list-status")
+            .build(),
+        entities
+            .binary_sensor("schema-drift", "Schema drift")
+            .entity_category(EntityCategory::Diagnostic)
+            .device_class("problem")
+            .value_template("{{ value_json.state }}")
+            .attributes_from("schema-drift")
+            .build(),
+    ];
+
+    configs.extend(TRIGGER_KEYS.iter().map(|key| entities.trigger(key).build()));
+    configs.push(
+        entities
+            .switch("tracking", "Tracking enabled")
+            .entity_category(EntityCategory::Config)
+            .icon("mdi:eye")
+            .build(),
+    );
+    configs.push(
+        entities
+            .text("url", "Product URL")
+            .entity_category(EntityCategory::Config)
+            .icon("mdi:link")
+            .build(),
+    );
+    configs
+}
+
+/// Discovery config of the circuit breaker's problem sensor, on a device of the tracker itself. Its
+/// node ID lacks the `tkpd-` prefix product nodes have, so it isn't read back as a product
+#[must_use]
+pub fn breaker_config() -> Value {
+    json!({
+        "platform": "binary_sensor",
+        "name": "Tokopedia requests paused",
+        "unique_id": "tkpdprice-breaker",
+        "state_topic": BREAKER_TOPIC,
+        "value_template": "{{ 'ON' if value_json.state == 'open' else 'OFF' }}",
+        "json_attributes_topic": BREAKER_TOPIC,
+        "device_class": "problem",
+        "entity_category": "diagnostic",
+        "icon": "mdi:electric-switch",
+        "device": {
+            "identifiers": "tkpdprice-tracker",
+            "name": "Tokopedia tracker",
+            "model": "ha-tkpd",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+/// Discovery config of the optional unrounded price sensor
+#[must_use]
+pub fn raw_price_config(product_hash: &str, device: &Value) -> (String, Value) {
+    DiscoveryBuilder::new(product_hash, device)
+        .sensor("raw-price", "Raw price")
+        .rupiah()
+        .build()
+}
+
+/// Discovery config of the optional campaign stock remaining sensor
+#[must_use]
+pub fn stock_percent_config(product_hash: &str, device: &Value) -> (String, Value) {
+    DiscoveryBuilder::new(product_hash, device)
+        .sensor("stock-remaining", "Stock remaining")
+        .state_class(StateClass::Measurement)
+        .unit("%")
+        .display_precision(0)
+        .icon("mdi:progress-check")
+        .build()
+}
+
+/// Starts the discovery configs of a product's entities, so every one of them gets the same device,
+/// topics under `tkpdprice/<hash>/` and unique IDs under `tkpdprice-<hash>-`
+#[derive(Clone, Copy, Debug)]
+pub struct DiscoveryBuilder<'a> {
+    product_hash: &'a str,
+    device: &'a Value,
+}
+
+impl<'a> DiscoveryBuilder<'a> {
+    #[must_use]
+    pub const fn new(product_hash: &'a str, device: &'a Value) -> Self {
+        Self {
+            product_hash,
+            device,
+        }
+    }
+
+    /// Topic a value of the product is published on
+    #[must_use]
+    pub fn topic(&self, key: &str) -> String {
+        format!("tkpdprice/{}/{key}", self.product_hash)
+    }
+
+    /// A sensor showing the value published on its key's topic
+    pub fn sensor(&self, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.entity("sensor", key, name)
+            .set("state_topic", self.topic(key))
+            .force_update(true)
+    }
+
+    /// A binary sensor reading `ON` or `OFF` from its key's topic
+    pub fn binary_sensor(&self, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.entity("binary_sensor", key, name)
+            .set("state_topic", self.topic(key))
+    }
+
+    /// A button publishing `PRESS` on its key's topic
+    pub fn button(&self, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.entity("button", key, name)
+            .set("command_topic", self.topic(key))
+    }
+
+    /// A switch whose commands HA retains on its key's topic, which is what the next run reads back
+    pub fn switch(&self, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.entity("switch", key, name)
+            .set("command_topic", self.topic(key))
+            .set("state_topic", self.topic(key))
+            .set("retain", true)
+    }
+
+    /// A text HA retains on its key's topic when edited, which is what the next run reads back
+    pub fn text(&self, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.entity("text", key, name)
+            .set("command_topic", self.topic(key))
+            .set("state_topic", self.topic(key))
+            .set("retain", true)
+    }
+
+    /// A device trigger fired by publishing its key on the product's trigger topic
+    pub fn trigger(&self, key: &str) -> EntityBuilder<'a> {
+        self.start(key)
+            .set("platform", "device_automation")
+            .set("automation_type", "trigger")
+            .set("topic", self.topic("trigger"))
+            .set("payload", key)
+            .set("type", key.replace('-', "_"))
+            .set("subtype", "product")
+    }
+
+    fn entity(&self, platform: &str, key: &str, name: &str) -> EntityBuilder<'a> {
+        self.start(key)
+            .set("platform", platform)
+            .unique_id_suffix(&key.replace('-', ""))
+            .set("name", name)
+    }
+
+    fn start(&self, key: &str) -> EntityBuilder<'a> {
+        let mut config = Map::new();
+        config.insert("device".to_string(), self.device.clone());
+        EntityBuilder {
+            product_hash: self.product_hash,
+            key: key.to_string(),
+            config,
+        }
+    }
+}
+
+/// The discovery config of one entity, see [`DiscoveryBuilder`]
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct EntityBuilder<'a> {
+    product_hash: &'a str,
+    key: String,
+    config: Map<String, Value>,
+}
+
+impl EntityBuilder<'_> {
+    pub fn device_class(self, device_class: &str) -> Self {
+        self.set("device_class", device_class)
+    }
+
+    pub fn unit(self, unit: &str) -> Self {
+        self.set("unit_of_measurement", unit)
+    }
+
+    pub fn state_class(self, state_class: StateClass) -> Self {
+        self.set("state_class", state_class.as_str())
+    }
+
+    pub fn entity_category(self, entity_category: EntityCategory) -> Self {
+        self.set("entity_category", entity_category.as_str())
+    }
+
+    pub fn icon(self, icon: &str) -> Self {
+        self.set("icon", icon)
+    }
+
+    pub fn display_precision(self, precision: u32) -> Self {
+        self.set("suggested_display_precision", precision)
+    }
+
+    /// A monetary sensor in IDR, shown in whole rupiah. ISO 4217 gives the rupiah two minor units
+    /// but sen haven't been in use for decades, Tokopedia prices never have them
+    pub fn rupiah(self) -> Self {
+        self.device_class("monetary")
+            .unit("IDR")
+            .display_precision(0)
+    }
+
+    /// Whether HA records a state update even when the value didn't change
+    pub fn force_update(self, force_update: bool) -> Self {
+        self.set("force_update", force_update)
+    }
+
+    pub fn enabled_by_default(self, enabled: bool) -> Self {
+        self.set("enabled_by_default", enabled)
+    }
+
+    /// Reads the entity's attributes from the product's attributes topic
+    pub fn attributes(self) -> Self {
+        self.attributes_from("attributes")
+    }
+
+    /// Reads the entity's attributes from the JSON published on another key's topic
+    pub fn attributes_from(self, key: &str) -> Self {
+        let topic = format!("tkpdprice/{}/{key}", self.product_hash);
+        self.set("json_attributes_topic", topic)
+    }
+
+    /// Picks the entity's state out of the payload
+    pub fn value_template(self, template: &str) -> Self {
+        self.set("value_template", template)
+    }
+
+    /// Picks the entity's attributes out of the attributes topic
+    pub fn attributes_template(self, template: &str) -> Self {
+        self.set("json_attributes_template", template)
+    }
+
+    /// Replaces the part of the unique ID after the product hash, which defaults to the key without
+    /// dashes. Changing it makes HA create a new entity
+    pub fn unique_id_suffix(self, suffix: &str) -> Self {
+        let unique_id = format!("tkpdprice-{}-{suffix}", self.product_hash);
+        self.set("unique_id", unique_id)
+    }
+
+    /// The topic suffix & discovery config
+    #[must_use]
+    pub fn build(self) -> (String, Value) {
+        (self.key, Value::Object(self.config))
+    }
+
+    fn set(mut self, field: &str, value: impl Into<Value>) -> Self {
+        self.config.insert(field.to_string(), value.into());
+        self
+    }
+}
+
+/// HA entity categories, entities without one are shown as controls or sensors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntityCategory {
+    Config,
+    Diagnostic,
+}
+
+impl EntityCategory {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Config => "config",
+            Self::Diagnostic => "diagnostic",
+        }
+    }
+}
+
+/// HA state classes, needed for long term statistics
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateClass {
+    Measurement,
+    Total,
+    TotalIncreasing,
+}
+
+impl StateClass {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Measurement => "measurement",
+            Self::Total => "total",
+            Self::TotalIncreasing => "total_increasing",
+        }
+    }
+}
+
+/// Points every sensor at the product's availability topic
+pub fn add_availability(configs: &mut [(String, Value)], product_hash: &str) {
+    for (_, config) in configs {
+        if config["platform"] == "sensor" {
+            config["availability_topic"] = json!(format!("tkpdprice/{product_hash}/availability"));
+        }
+    }
+}
+
+/// Suggests readable entity IDs, `<platform>.tkpd_<slug>_<key>`, for every entity. HA only uses them
+/// when it first sees an entity, unique IDs are what identify it afterwards
+pub fn add_entity_ids(configs: &mut [(String, Value)], slug: &str) {
+    for (key, config) in configs {
+        let Some(platform) = config["platform"].as_str() else {
+            continue;
+        };
+        if platform == "device_automation" {
+            continue;
+        }
+        let key: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        config["default_entity_id"] = json!(format!("{platform}.tkpd_{slug}_{key}"));
+    }
+}
+
+/// Applies user overrides to the matching configs, warning about overrides matching nothing
+pub fn apply_overrides(configs: &mut [(String, Value)], overrides: &[SensorOverride]) {
+    for sensor_override in overrides {
+        if let Some((_, config)) = configs
+            .iter_mut()
+            .find(|(key, _)| *key == sensor_override.key)
+        {
+            sensor_override.apply(config);
+        } else {
+            warn!(
+                "No sensor {:?} to apply the {} override to",
+                sensor_override.key, sensor_override.field
+            );
+        }
+    }
+}
+
+/// A user defined sensor whose state is read from the product data with a simple JSON path
+#[derive(Clone, Debug)]
+pub struct ExtraSensor {
+    pub name: String,
+    path: Vec<PathSegment>,
+}
+
+#[derive(Clone, Debug)]
+enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+impl ExtraSensor {
+    /// Parses a `NAME=PATH` definition, where `PATH` is a dot separated path like `$.campaign.stock`
+    ///
+    /// # Errors
+    ///
+    /// Fails when the name or path is missing or an array index isn't a number.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some((name, path)) = s.split_once('=') else {
+            return Err("expected NAME=PATH".to_string());
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("sensor name is empty".to_string());
+        }
+
+        let path = path.trim();
+        let path = path.strip_prefix('$').unwrap_or(path);
+        let mut segments = Vec::new();
+        for part in path.split('.').filter(|p| !p.is_empty()) {
+            let (field, indexes) = part
+                .split_once('[')
+                .map_or((part, None), |(f, i)| (f, Some(i)));
+            if !field.is_empty() {
+                segments.push(PathSegment::Field(field.to_string()));
+            }
+            for index in indexes.into_iter().flat_map(|i| i.split('[')) {
+                let index = index
+                    .strip_suffix(']')
+                    .and_then(|i| i.parse().ok())
+                    .ok_or_else(|| format!("invalid array index in {part:?}"))?;
+                segments.push(PathSegment::Index(index));
+            }
+        }
+        if segments.is_empty() {
+            return Err("path is empty".to_string());
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            path: segments,
+        })
+    }
+
+    /// Topic & object ID suffix, derived from the sensor name
+    #[must_use]
+    pub fn key(&self) -> String {
+        let slug = self
+            .name
+            .to_lowercase()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>()
+            .join("-");
+        format!("extra-{slug}")
+    }
+
+    /// Reads the sensor state, strings are published as-is and anything else as JSON
+    #[must_use]
+    pub fn extract(&self, data: &Value) -> Option<String> {
+        let value = self
+            .path
+            .iter()
+            .try_fold(data, |value, segment| match segment {
+                PathSegment::Field(field) => value.get(field),
+                PathSegment::Index(index) => value.get(index),
+            });
+        match value {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(other) => Some(other.to_string()),
+            None => {
+                warn!(
+                    "Extra sensor {:?} path not found in product data",
+                    self.name
+                );
+                None
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn discovery_config(&self, product_hash: &str, device: &Value) -> (String, Value) {
+        let key = self.key();
+        DiscoveryBuilder::new(product_hash, device)
+            .sensor(&key, &self.name)
+            .unique_id_suffix(&key)
+            .build()
+    }
+}
+
+/// Discovery fields that can be overridden per sensor
+pub const OVERRIDABLE_FIELDS: &[&str] = &[
+    "icon",
+    "device_class",
+    "unit_of_measurement",
+    "entity_category",
+    "enabled_by_default",
+    "suggested_display_precision",
+];
+
+/// A user override of one discovery field of a sensor
+#[derive(Clone, Debug)]
+pub struct SensorOverride {
+    key: String,
+    field: String,
+    /// `None` removes the field from the discovery config
+    value: Option<Value>,
+}
+
+impl SensorOverride {
+    /// Parses a `KEY.FIELD=VALUE` override
+    ///
+    /// # Errors
+    ///
+    /// Fails on malformed overrides, fields that can't be overridden and invalid values.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some((target, value)) = s.split_once('=') else {
+            return Err("expected KEY.FIELD=VALUE".to_string());
+        };
+        let Some((key, field)) = target.trim().rsplit_once('.') else {
+            return Err("expected KEY.FIELD, e.g. stock.icon".to_string());
+        };
+        if !OVERRIDABLE_FIELDS.contains(&field) {
+            return Err(format!(
+                "{field:?} can't be overridden, expected one of {}",
+                OVERRIDABLE_FIELDS.join(", ")
+            ));
+        }
+
+        let value = value.trim();
+        let value = if value.is_empty() {
+            None
+        } else if field == "suggested_display_precision" {
+            let precision = value
+                .parse::<u64>()
+                .map_err(|_| "display precision must be a positive integer".to_string())?;
+            Some(json!(precision))
+        } else if field == "enabled_by_default" {
+            let enabled = value
+                .parse::<bool>()
+                .map_err(|_| "enabled_by_default must be true or false".to_string())?;
+            Some(json!(enabled))
+        } else {
+            Some(json!(value))
+        };
+
+        Ok(Self {
+            key: key.to_string(),
+            field: field.to_string(),
+            value,
+        })
+    }
+
+    fn apply(&self, config: &mut Value) {
+        let Some(config) = config.as_object_mut() else {
+            return;
+        };
+        match &self.value {
+            Some(value) => config.insert(self.field.clone(), value.clone()),
+            None => config.remove(&self.field),
+        };
+    }
+}
+
+// https://stackoverflow.com/questions/27650312/show-u8-slice-in-hex-representation
+struct HexSlice<'a>(&'a [u8]);
+
+impl fmt::LowerHex for HexSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "0x")?;
+        }
+        for &byte in self.0 {
+            write!(f, "{byte:0>2x}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Value {
+        json!({
+            "campaign": { "stock": 12, "campaignTypeName": "Flash Sale" },
+            "variants": [{ "options": ["Red", "Blue"] }],
+        })
+    }
+
+    #[test]
+    fn extra_sensor_reads_fields() {
+        let sensor = ExtraSensor::parse(" Campaign stock = $.campaign.stock ").unwrap();
+        assert_eq!(sensor.name, "Campaign stock");
+        assert_eq!(sensor.key(), "extra-campaign-stock");
+        assert_eq!(sensor.extract(&data()).as_deref(), Some("12"));
+    }
+
+    #[test]
+    fn extra_sensor_publishes_strings_unquoted() {
+        let sensor = ExtraSensor::parse("Type=campaign.campaignTypeName").unwrap();
+        assert_eq!(sensor.extract(&data()).as_deref(), Some("Flash Sale"));
+        let sensor = ExtraSensor::parse("Campaign=$.campaign").unwrap();
+        assert_eq!(
+            sensor.extract(&data()).as_deref(),
+            Some(r#"{"campaignTypeName":"Flash Sale","stock":12}"#)
+        );
+    }
+
+    #[test]
+    fn extra_sensor_reads_array_indexes() {
+        let sensor = ExtraSensor::parse("Colour=$.variants[0].options[1]").unwrap();
+        assert_eq!(sensor.extract(&data()).as_deref(), Some("Blue"));
+        let sensor = ExtraSensor::parse("Colour=$.variants[0].options[5]").unwrap();
+        assert_eq!(sensor.extract(&data()), None);
+    }
+
+    #[test]
+    fn extra_sensor_misses_paths_not_in_the_data() {
+        let sensor = ExtraSensor::parse("Shop=$.shop.name").unwrap();
+        assert_eq!(sensor.extract(&data()), None);
+    }
+
+    #[test]
+    fn extra_sensor_refuses_malformed_definitions() {
+        let error = |s| ExtraSensor::parse(s).unwrap_err();
+        assert_eq!(error("$.campaign.stock"), "expected NAME=PATH");
+        assert_eq!(error(" =$.campaign.stock"), "sensor name is empty");
+        assert_eq!(error("Stock=$"), "path is empty");
+        assert_eq!(error("Stock=.."), "path is empty");
+        assert_eq!(
+            error("Colour=$.variants[x]"),
+            "invalid array index in \"variants[x]\""
+        );
+        assert_eq!(
+            error("Colour=$.variants[0"),
+            "invalid array index in \"variants[0\""
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! `--history-csv` price history, optionally signed so a shared copy can be checked for edits.
+//!
+//! With `--history-key`, every row ends with a keyed BLAKE2s hash of the rest of the row. Anyone
+//! holding the key can run `verify-history` to find rows that were changed or made up.
+//! `compact-history` rolls old rows into daily aggregates kept in a file of their own.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write as _;
+use std::path::Path;
+
+use blake2::Blake2sMac256;
+use blake2::digest::Mac;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use log::{info, warn};
+use tkpd_tracker::TrackerError;
+
+const HEADER: &str = "time,product,name,price,stock";
+const DAILY_HEADER: &str = "date,product,name,min,avg,max,rows";
+
+/// Signs rows with the key read from a file
+pub struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    /// Reads the key, which is used as is and can be up to 32 bytes
+    pub fn load(path: &Path) -> Result<Self, TrackerError> {
+        let key = std::fs::read(path).map_err(TrackerError::io(format!(
+            "Unable to read history key {}",
+            path.display()
+        )))?;
+        if key.is_empty() || key.len() > 32 {
+            return Err(TrackerError::Usage(format!(
+                "History key {} must be 1 to 32 bytes, it is {}",
+                path.display(),
+                key.len()
+            )));
+        }
+        Ok(Self { key })
+    }
+
+    /// Hex signature of a row, without its line break
+    fn sign(&self, row: &str) -> String {
+        // Only fails for keys over 32 bytes, which `load` refuses
+        let mut mac = Blake2sMac256::new_from_slice(&self.key).expect("key of at most 32 bytes");
+        mac.update(row.as_bytes());
+        let mut signature = String::with_capacity(64);
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(signature, "{byte:02x}");
+        }
+        signature
+    }
+}
+
+/// Locks `<history>.lock` until dropped. Runs appending and `compact` rewriting the history take
+/// it in turn, so no row is appended to a history that is being replaced
+fn lock(path: &Path) -> Result<File, TrackerError> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .and_then(|file| file.lock().map(|()| file))
+        .map_err(TrackerError::io(format!(
+            "Unable to lock {}",
+            path.display()
+        )))?;
+    Ok(file)
+}
+
+/// Appends a `time,product,name,price,stock` row, writing the header when the file is new. A
+/// `signature` column is added when signing, and the stock is left empty when unknown
+pub fn append_csv(
+    path: &Path,
+    signer: Option<&Signer>,
+    time: DateTime<Utc>,
+    product_hash: &str,
+    name: &str,
+    price: i64,
+    stock: Option<i64>,
+) -> Result<(), TrackerError> {
+    let _lock = lock(path)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(TrackerError::io(format!(
+            "Unable to open {}",
+            path.display()
+        )))?;
+    let is_new = file.metadata().is_ok_and(|m| m.len() == 0);
+
+    let name = quote(name);
+    let header = match (is_new, signer) {
+        (false, _) => String::new(),
+        (true, None) => format!("{HEADER}\n"),
+        (true, Some(_)) => format!("{HEADER},signature\n"),
+    };
+    let time = time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let stock = stock.map_or_else(String::new, |stock| stock.to_string());
+    let mut row = format!("{time},{product_hash},{name},{price},{stock}");
+    if let Some(signer) = signer {
+        let signature = signer.sign(&row);
+        row = format!("{row},{signature}");
+    }
+    file.write_all(format!("{header}{row}\n").as_bytes())
+        .map_err(TrackerError::io(format!(
+            "Unable to append to {}",
+            path.display()
+        )))
+}
+
+/// Checks every row of a signed history against its signature, logging the ones that don't match
+pub fn verify(path: &Path, signer: &Signer) -> Result<(), TrackerError> {
+    let history = std::fs::read_to_string(path).map_err(TrackerError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let mut lines = history.lines().enumerate();
+    if lines
+        .next()
+        .is_none_or(|(_, header)| header != format!("{HEADER},signature"))
+    {
+        return Err(TrackerError::Usage(format!(
+            "{} isn't a signed price history",
+            path.display()
+        )));
+    }
+
+    let mut rows = 0;
+    let mut tampered = 0;
+    for (index, line) in lines.filter(|(_, line)| !line.is_empty()) {
+        rows += 1;
+        let matching = line
+            .rsplit_once(',')
+            .is_some_and(|(row, signature)| signer.sign(row) == signature);
+        if !matching {
+            warn!("Line {} doesn't match its signature: {line}", index + 1);
+            tampered += 1;
+        }
+    }
+
+    if tampered > 0 {
+        return Err(TrackerError::Tampered(tampered));
+    }
+    info!(
+        "All {rows} rows of {} match their signature",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Prices of one product on one day, rolled up by [`compact`]
+struct Day {
+    name: String,
+    min: i64,
+    max: i64,
+    sum: i64,
+    rows: i64,
+}
+
+/// Rolls the rows of days before the last `keep_days` into one `date,product,name,min,avg,max,rows`
+/// row per product & day, appended to `daily`. The history keeps its newer rows unchanged, so
+/// their signatures still match
+pub fn compact(path: &Path, daily: &Path, keep_days: u32) -> Result<(), TrackerError> {
+    let _lock = lock(path)?;
+    let history = std::fs::read_to_string(path).map_err(TrackerError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let mut lines = history.lines();
+    let Some(header) = lines.next().filter(|header| header.starts_with(HEADER)) else {
+        return Err(TrackerError::Usage(format!(
+            "{} isn't a price history",
+            path.display()
+        )));
+    };
+
+    let cutoff = (Utc::now() - TimeDelta::days(keep_days.into())).date_naive();
+    let mut kept = vec![header];
+    let mut days: BTreeMap<(NaiveDate, String), Day> = BTreeMap::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let fields = fields(line);
+        let row = match fields.as_slice() {
+            [time, product, name, price, ..] => DateTime::parse_from_rfc3339(time)
+                .ok()
+                .zip(price.parse::<i64>().ok())
+                .map(|(time, price)| (time.with_timezone(&Utc).date_naive(), product, name, price)),
+            _ => None,
+        };
+        // Rows this can't read are kept as they are
+        let Some((date, product, name, price)) = row.filter(|(date, ..)| *date < cutoff) else {
+            kept.push(line);
+            continue;
+        };
+        let day = days.entry((date, product.clone())).or_insert(Day {
+            name: String::new(),
+            min: price,
+            max: price,
+            sum: 0,
+            rows: 0,
+        });
+        day.name.clone_from(name);
+        day.min = day.min.min(price);
+        day.max = day.max.max(price);
+        day.sum += price;
+        day.rows += 1;
+    }
+    if days.is_empty() {
+        info!("No rows older than {keep_days} days in {}", path.display());
+        return Ok(());
+    }
+
+    let mut rows = String::new();
+    if !std::fs::metadata(daily).is_ok_and(|m| m.len() > 0) {
+        let _ = writeln!(rows, "{DAILY_HEADER}");
+    }
+    for ((date, product), day) in &days {
+        let avg = (day.sum + day.rows / 2) / day.rows;
+        let _ = writeln!(
+            rows,
+            "{date},{product},{},{},{avg},{},{}",
+            quote(&day.name),
+            day.min,
+            day.max,
+            day.rows
+        );
+    }
+
+    // Rewritten before the daily rows are appended, so a failure leaves the old rows to a later
+    // compaction instead of counting them twice. Runs wait on the lock rather than append meanwhile
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let compacted = kept.iter().fold(String::new(), |mut compacted, line| {
+        let _ = writeln!(compacted, "{line}");
+        compacted
+    });
+    std::fs::write(&temporary, compacted)
+        .and_then(|()| std::fs::rename(&temporary, path))
+        .map_err(TrackerError::io(format!(
+            "Unable to rewrite {}",
+            path.display()
+        )))?;
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(daily)
+        .and_then(|mut file| file.write_all(rows.as_bytes()))
+        .map_err(TrackerError::io(format!(
+            "Unable to append to {}",
+            daily.display()
+        )))?;
+    info!(
+        "Rolled {} rows into {} daily rows of {}",
+        days.values().map(|day| day.rows).sum::<i64>(),
+        days.len(),
+        daily.display()
+    );
+    Ok(())
+}
+
+/// A CSV field, quoted when it holds a separator, quote or line break
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits a row into its fields, unquoting them
+fn fields(row: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history file of its own per test, removed with its lock file when dropped
+    struct TempHistory(std::path::PathBuf);
+
+    impl TempHistory {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("ha-tkpd-test-{}-{name}.csv", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempHistory {
+        fn drop(&mut self) {
+            let mut lock_path = self.0.as_os_str().to_owned();
+            lock_path.push(".lock");
+            let _ = std::fs::remove_file(&self.0);
+            let _ = std::fs::remove_file(lock_path);
+        }
+    }
+
+    fn signer() -> Signer {
+        Signer {
+            key: b"test key".to_vec(),
+        }
+    }
+
+    fn append(history: &TempHistory, name: &str, price: i64) {
+        let time = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        append_csv(
+            &history.0,
+            Some(&signer()),
+            time,
+            "abcd1234",
+            name,
+            price,
+            Some(3),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn signed_rows_verify() {
+        let history = TempHistory::new("signed");
+        append(&history, "Lamp", 1000);
+        append(&history, "Lamp", 1500);
+        assert!(verify(&history.0, &signer()).is_ok());
+    }
+
+    #[test]
+    fn quoted_names_with_commas_verify() {
+        let history = TempHistory::new("quoted");
+        append(&history, "Lamp, big \"XL\"", 1000);
+        let rows = std::fs::read_to_string(&history.0).unwrap();
+        assert!(rows.contains(",\"Lamp, big \"\"XL\"\"\",1000,3,"));
+        assert!(verify(&history.0, &signer()).is_ok());
+    }
+
+    #[test]
+    fn edited_rows_fail() {
+        let history = TempHistory::new("edited");
+        append(&history, "Lamp", 1000);
+        append(&history, "Lamp", 1500);
+        let rows = std::fs::read_to_string(&history.0).unwrap();
+        std::fs::write(&history.0, rows.replacen(",1000,", ",900,", 1)).unwrap();
+        assert!(matches!(
+            verify(&history.0, &signer()),
+            Err(TrackerError::Tampered(1))
+        ));
+    }
+
+    #[test]
+    fn other_keys_fail() {
+        let history = TempHistory::new("key");
+        append(&history, "Lamp", 1000);
+        let other = Signer {
+            key: b"other key".to_vec(),
+        };
+        assert!(matches!(
+            verify(&history.0, &other),
+            Err(TrackerError::Tampered(1))
+        ));
+    }
+
+    #[test]
+    fn unsigned_histories_are_refused() {
+        let history = TempHistory::new("unsigned");
+        append_csv(&history.0, None, Utc::now(), "abcd1234", "Lamp", 1000, None).unwrap();
+        assert!(matches!(
+            verify(&history.0, &signer()),
+            Err(TrackerError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn fields_unquote() {
+        assert_eq!(
+            fields(&format!("a,{},c", quote("b, \"d\""))),
+            ["a", "b, \"d\"", "c"]
+        );
+    }
+}
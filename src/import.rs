@@ -0,0 +1,157 @@
+//! `import` of product URLs from a text file, such as an exported bookmark list, into the config file
+//! or straight to tracking, and `adopt` of the products already tracked on the broker.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::Path;
+
+use log::{info, warn};
+use tkpd_tracker::{PriceSource, Publisher, TrackerError, ha, state};
+
+use crate::cli::{self, Args};
+
+/// Reads the URLs to import, one per line. Blank lines, `#` comments, URLs that aren't a Tokopedia
+/// listing and products already listed or seen earlier in the file are skipped
+pub fn read_urls(
+    path: &Path,
+    source: &impl PriceSource,
+    known: &[String],
+) -> Result<Vec<String>, TrackerError> {
+    let urls = std::fs::read_to_string(path).map_err(TrackerError::io(format!(
+        "Unable to read {}",
+        path.display()
+    )))?;
+    let lines = urls
+        .lines()
+        .enumerate()
+        .map(|(number, line)| (format!("line {}", number + 1), line.trim()))
+        .filter(|(_, url)| !url.is_empty() && !url.starts_with('#'));
+    Ok(new_urls(lines, source, known))
+}
+
+/// The URLs, each with where it was read from, whose product isn't among the `known` URLs or
+/// earlier ones
+fn new_urls<'a>(
+    urls: impl Iterator<Item = (String, &'a str)>,
+    source: &impl PriceSource,
+    known: &[String],
+) -> Vec<String> {
+    let mut seen: HashSet<String> = known
+        .iter()
+        .filter_map(|url| source.parse_url(url).ok())
+        .map(|target| ha::product_hash(&target.shop, &target.key))
+        .collect();
+
+    let mut imported = Vec::new();
+    for (origin, url) in urls {
+        match source.parse_url(url) {
+            Ok(target) => {
+                if seen.insert(ha::product_hash(&target.shop, &target.key)) {
+                    imported.push(url.to_string());
+                }
+            }
+            Err(e) => warn!("Skipping {origin} - {e}"),
+        }
+    }
+    imported
+}
+
+/// The config file of `import` & `adopt`, along with the URLs of its products
+fn config_file<'a>(args: &'a Args, usage: &str) -> Result<(&'a Path, Vec<String>), TrackerError> {
+    let config = args
+        .config
+        .as_deref()
+        .ok_or_else(|| TrackerError::Usage(usage.to_string()))?;
+    let configured = cli::product_urls(config)?
+        .into_iter()
+        .map(|(url, _)| url)
+        .collect();
+    Ok((config, configured))
+}
+
+/// Adds the URLs of `file` that the config file doesn't list yet as `[[products]]` entries
+pub fn to_config(args: &Args, file: &Path) -> Result<(), TrackerError> {
+    let (config, configured) = config_file(
+        args,
+        "import adds to the --config file, or tracks the products right away with --track",
+    )?;
+    let urls = read_urls(file, &args.price_source()?, &configured)?;
+    if urls.is_empty() {
+        info!(
+            "Every product of {} is already in {}",
+            file.display(),
+            config.display()
+        );
+        return Ok(());
+    }
+    append_products(config, &urls)
+}
+
+/// Adds the products tracked on the broker that the config file doesn't list yet, with the URL
+/// their device links to
+pub async fn adopt(publisher: Publisher, args: &Args) -> Result<(), TrackerError> {
+    let (config, configured) = config_file(args, "adopt adds to the --config file")?;
+    let discovery_prefix = args.ha_mqtt_discovery_topic.as_str();
+    let retained = publisher
+        .read_retained(&state::topic_filters(discovery_prefix))
+        .await?;
+    publisher.disconnect().await?;
+
+    let state = state::from_retained(retained, discovery_prefix);
+    let products = state["products"].as_object().cloned().unwrap_or_default();
+    let mut tracked = Vec::new();
+    for (product_hash, product) in &products {
+        if let Some(url) = product["configuration_url"].as_str() {
+            tracked.push((product_hash.clone(), url));
+        } else {
+            warn!("Skipping {product_hash} - its device has no configuration URL");
+        }
+    }
+    let urls = new_urls(tracked.into_iter(), &args.price_source()?, &configured);
+    if urls.is_empty() {
+        info!(
+            "Every product on the broker is already in {}",
+            config.display()
+        );
+        return Ok(());
+    }
+    append_products(config, &urls)
+}
+
+/// Appends a `[[products]]` entry of each URL to the config file
+fn append_products(config: &Path, urls: &[String]) -> Result<(), TrackerError> {
+    let mut entries = String::new();
+    for url in urls {
+        let url = toml::Value::String(url.clone());
+        let _ = write!(entries, "\n[[products]]\nurl = {url}\n");
+    }
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(config)
+        .and_then(|mut file| file.write_all(entries.as_bytes()))
+        .map_err(TrackerError::io(format!(
+            "Unable to append to {}",
+            config.display()
+        )))?;
+    info!("Added {} products to {}", urls.len(), config.display());
+    Ok(())
+}
+
+/// Tracks every product of `file` now, with the options given to `import`
+pub async fn track(publisher: Publisher, args: &Args, file: &Path) -> Result<(), TrackerError> {
+    let urls = read_urls(file, &args.price_source()?, &[])?;
+    let mut failure = None;
+    let mut failed = 0;
+    for url in &urls {
+        if let Err(e) = crate::track_url(&publisher, args, url).await {
+            warn!("Tracking {url} failed - {e}");
+            failure.get_or_insert(e);
+            failed += 1;
+        }
+    }
+    crate::set_log_context(None);
+    info!("Tracked {} of {} products", urls.len() - failed, urls.len());
+    publisher.disconnect().await?;
+    failure.map_or(Ok(()), Err)
+}
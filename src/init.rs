@@ -0,0 +1,247 @@
+//! `init` wizard, asking for the broker & products on a terminal and writing them as a config file.
+//!
+//! Flags given alongside `init` are offered as the default answers. The broker and every product are
+//! tried before the file is written, an answer that doesn't work can still be kept.
+
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use log::warn;
+use tkpd_tracker::{PriceSource, Publisher, TrackerError};
+
+use crate::cli::Args;
+use crate::summary;
+
+/// Asks for a config, checks it works and writes it to `path`
+pub async fn run(mut args: Args, path: &Path) -> Result<(), TrackerError> {
+    if !std::io::stdin().is_terminal() {
+        return Err(TrackerError::Usage(
+            "init asks its questions on a terminal, write the config file by hand instead"
+                .to_string(),
+        ));
+    }
+    if path.exists() && !confirm(&format!("{} exists, overwrite it?", path.display()))? {
+        return Ok(());
+    }
+
+    loop {
+        ask_broker(&mut args)?;
+        eprintln!("Connecting to {}:{}...", args.mqtt_server, args.mqtt_port);
+        match check_broker(&args).await {
+            Ok(()) => {
+                eprintln!("Connected");
+                break;
+            }
+            Err(e) => {
+                warn!("{e}");
+                if confirm("Keep these broker settings anyway?")? {
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut urls = Vec::new();
+    loop {
+        let url = ask("Product URL, empty when done", None)?;
+        if url.is_empty() {
+            if urls.is_empty() {
+                eprintln!("At least one product is needed");
+                continue;
+            }
+            break;
+        }
+        match check_product(&args, &url).await {
+            Ok(found) => eprintln!("Found {found}"),
+            Err(e) => {
+                warn!("{e}");
+                if !confirm("Keep this product anyway?")? {
+                    continue;
+                }
+            }
+        }
+        urls.push(url);
+    }
+
+    write_config(path, &config(&args, &urls))?;
+    eprintln!(
+        "Wrote {}, track its products with `{} --config {}`",
+        path.display(),
+        env!("CARGO_BIN_NAME"),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Asks for the broker connection, defaulting to the flags given
+fn ask_broker(args: &mut Args) -> Result<(), TrackerError> {
+    args.mqtt_server = ask("MQTT broker host", Some(&args.mqtt_server))?;
+    args.mqtt_port = loop {
+        match ask("MQTT broker port", Some(&args.mqtt_port.to_string()))?.parse() {
+            Ok(port) => break port,
+            Err(e) => eprintln!("Invalid port - {e}"),
+        }
+    };
+    let username = ask("MQTT username, - for none", args.mqtt_username.as_deref())?;
+    args.mqtt_username = Some(username).filter(|username| !username.is_empty() && username != "-");
+    args.mqtt_password = match &args.mqtt_username {
+        // Kept as the file, which the config then points at
+        Some(_) if args.mqtt_password_file.is_some() => None,
+        // Not shown as the default, a password given as a flag stays out of the terminal
+        Some(_) if args.mqtt_password.is_some() => {
+            Some(ask_hidden("MQTT password, empty to keep the given one")?)
+                .filter(|password| !password.is_empty())
+                .or_else(|| args.mqtt_password.clone())
+        }
+        Some(_) => Some(ask_hidden("MQTT password")?),
+        None => None,
+    };
+    args.ha_mqtt_discovery_topic = ask(
+        "Home Assistant discovery prefix",
+        Some(&args.ha_mqtt_discovery_topic),
+    )?;
+    Ok(())
+}
+
+async fn check_broker(args: &Args) -> Result<(), TrackerError> {
+    let publisher = Publisher::connect_fail_fast(args.mqtt_options()?);
+    publisher.wait_connected().await?;
+    publisher.disconnect().await
+}
+
+/// The name & price of the product, to show the URL points at what the user meant
+async fn check_product(args: &Args, url: &str) -> Result<String, TrackerError> {
+    let source = args.price_source()?;
+    let product = source.fetch(&source.parse_url(url)?).await?;
+    Ok(format!(
+        "\"{}\" at {}",
+        product.name,
+        summary::format_idr(product.price)
+    ))
+}
+
+/// The config file, leaving out the discovery prefix when it is the default
+fn config(args: &Args, urls: &[String]) -> String {
+    let mut config = format!(
+        "# Written by `{} init`\n\nserver = {}\nport = {}\n",
+        env!("CARGO_BIN_NAME"),
+        quoted(&args.mqtt_server),
+        args.mqtt_port
+    );
+    if let Some(username) = &args.mqtt_username {
+        let _ = writeln!(config, "username = {}", quoted(username));
+    }
+    if let Some(password) = &args.mqtt_password {
+        let _ = writeln!(config, "password = {}", quoted(password));
+    }
+    if let Some(path) = &args.mqtt_password_file {
+        let path = path.to_string_lossy();
+        let _ = writeln!(config, "password-file = {}", quoted(&path));
+    }
+    if args.ha_mqtt_discovery_topic != "homeassistant" {
+        let _ = writeln!(config, "topic = {}", quoted(&args.ha_mqtt_discovery_topic));
+    }
+    for url in urls {
+        let _ = writeln!(config, "\n[[products]]\nurl = {}", quoted(url));
+    }
+    config
+}
+
+fn quoted(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+/// Writes the config readable by its owner only, it can hold the broker password
+fn write_config(path: &Path, config: &str) -> Result<(), TrackerError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(config.as_bytes()))
+        .map_err(TrackerError::io(format!(
+            "Unable to write {}",
+            path.display()
+        )))
+}
+
+/// Prompts on stderr and reads a trimmed answer, `default` when it's left empty
+fn ask(question: &str, default: Option<&str>) -> Result<String, TrackerError> {
+    match default {
+        Some(default) if !default.is_empty() => eprint!("{question} [{default}]: "),
+        _ => eprint!("{question}: "),
+    }
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    let read = std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(TrackerError::io("Unable to read the answer"))?;
+    if read == 0 {
+        return Err(TrackerError::Usage(
+            "Input ended before every question was answered".to_string(),
+        ));
+    }
+    match answer.trim() {
+        "" => Ok(default.unwrap_or_default().to_string()),
+        answer => Ok(answer.to_string()),
+    }
+}
+
+/// Raw mode, left again however the answer ends
+struct RawMode;
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Prompts on stderr and reads an answer without echoing it, for passwords
+fn ask_hidden(question: &str) -> Result<String, TrackerError> {
+    eprint!("{question}: ");
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    {
+        terminal::enable_raw_mode().map_err(TrackerError::io(
+            "Unable to hide the answer on the terminal",
+        ))?;
+        let _raw_mode = RawMode;
+        loop {
+            let Event::Key(key) =
+                event::read().map_err(TrackerError::io("Unable to read the answer"))?
+            else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Enter => break,
+                KeyCode::Char('c' | 'd') if ctrl => {
+                    eprint!("\r\n");
+                    return Err(TrackerError::Usage(
+                        "Input ended before every question was answered".to_string(),
+                    ));
+                }
+                KeyCode::Backspace => {
+                    answer.pop();
+                }
+                KeyCode::Char(c) if !ctrl => answer.push(c),
+                _ => {}
+            }
+        }
+    }
+    eprintln!();
+    Ok(answer.trim().to_string())
+}
+
+fn confirm(question: &str) -> Result<bool, TrackerError> {
+    let answer = ask(&format!("{question} [y/N]"), None)?;
+    Ok(matches!(answer.to_ascii_lowercase().as_str(), "y" | "yes"))
+}
@@ -0,0 +1,38 @@
+//! Tracks Tokopedia product prices on Home Assistant via MQTT.
+//!
+//! The `ha-tkpd` binary is a thin CLI over this crate. Embedders fetch a listing through a
+//! [`PriceSource`] such as [`Tokopedia`], build its discovery configs with [`ha`] and publish them
+//! through a [`Publisher`].
+//!
+//! - [`source`] is the marketplace-neutral [`ProductSnapshot`] every [`PriceSource`] returns
+//! - [`tokopedia`] talks to Tokopedia and knows nothing about Home Assistant
+//! - [`ha`] builds discovery payloads and knows nothing about MQTT connections
+//! - [`mqtt`] moves retained topics to and from the broker
+
+#![deny(clippy::all)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::nursery)]
+#![warn(clippy::cargo)]
+#![warn(clippy::perf)]
+#![warn(clippy::complexity)]
+#![warn(clippy::style)]
+#![allow(clippy::multiple_crate_versions)]
+#![allow(clippy::too_many_lines)]
+
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("Tokopedia is only reachable over HTTPS, enable the rustls or native-tls feature");
+
+pub mod discovery_lint;
+pub mod error;
+pub mod ha;
+pub mod model;
+pub mod mqtt;
+pub mod name_cleanup;
+pub mod source;
+pub mod state;
+pub mod tokopedia;
+
+pub use error::TrackerError;
+pub use mqtt::Publisher;
+pub use source::{Campaign, PriceSource, ProductSnapshot, Target};
+pub use tokopedia::{Tokopedia, fetch_product, parse_product_url};
@@ -8,534 +8,1729 @@
 #![allow(clippy::multiple_crate_versions)]
 #![allow(clippy::too_many_lines)]
 
-use std::fmt;
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
-use blake2::Blake2sVar;
-use blake2::digest::VariableOutput;
-use chrono::Utc;
-use clap::{Parser, ValueHint, command};
-use log::{debug, error, info, trace, warn};
-use reqwest::blocking::Client;
-use reqwest::header::{ACCEPT, HOST, HeaderMap, HeaderValue, REFERER};
-use rumqttc::MqttOptions;
+use breaker::Breaker;
+use chrono::{DateTime, TimeDelta, Utc};
+use cli::{Action, Args, LogFormat, OutputFormat, default_instance_id};
+use log::{LevelFilter, debug, error, info, warn};
+use rumqttc::{Publish, QoS};
 use serde_json::{Value, json};
+use sink::Sink;
+use status::{RunStatus, Stage};
+use summary::Summary;
+use tkpd_tracker::ha::{self, TRIGGER_KEYS};
+use tkpd_tracker::{
+    Campaign, PriceSource, ProductSnapshot, Publisher, Target, TrackerError, discovery_lint,
+    name_cleanup, state,
+};
+use tokio::sync::broadcast::error::RecvError;
 
-/// Tracks Tokopedia item prices via Home Assistant
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// The Tokopedia URL for a price to be tracked
-    #[arg(value_hint(ValueHint::Url))]
-    url: String,
-
-    /// MQTT Broker username if required
-    #[arg(long("username"), short('u'), value_hint(ValueHint::Username))]
-    mqtt_username: Option<String>,
-    /// MQTT Broker password if required
-    #[arg(long("password"), short('p'))]
-    mqtt_password: Option<String>,
-
-    /// MQTT Broker host or IP
-    #[arg(
-        long("server"),
-        short('s'),
-        value_hint(ValueHint::Hostname),
-        default_value = "localhost"
-    )]
-    mqtt_server: String,
-    /// MQTT Broker port
-    #[arg(long("port"), short('x'), default_value_t = 1883)]
-    mqtt_port: u16,
-
-    /// HA MQTT autodiscover topic
-    #[arg(long("topic"), short('t'), default_value = "homeassistant")]
-    ha_mqtt_discovery_topic: String,
-
-    /// When set, deletes existing data & connection from HA
-    #[arg(long("delete"), short('d'))]
-    unretain: bool,
-}
-
-const TKPD_GQL_ENDPOINT: &str = "https://gql.tokopedia.com/graphql/PDPGetLayoutQuery";
-const GQL_PDP_OPNAME: &str = "PDPGetLayoutQuery";
-const GQL_PDP_QUERY: &str = "fragment ProductHighlight on pdpDataProductContent {\n  name\n  price {\n    value\n    currency\n    priceFmt\n    slashPriceFmt\n    discPercentage\n    __typename\n  }\n  campaign {\n    campaignID\n    campaignType\n    campaignTypeName\n    campaignIdentifier\n    background\n    percentageAmount\n    originalPrice\n    discountedPrice\n    originalStock\n    stock\n    stockSoldPercentage\n    threshold\n    startDate\n    endDate\n    endDateUnix\n    appLinks\n    isAppsOnly\n    isActive\n    hideGimmick\n    showStockBar\n    __typename\n  }\n  thematicCampaign {\n    additionalInfo\n    background\n    campaignName\n    icon\n    __typename\n  }\n  stock {\n    useStock\n    value\n    stockWording\n    __typename\n  }\n  variant {\n    isVariant\n    parentID\n    __typename\n  }\n  wholesale {\n    minQty\n    price {\n      value\n      currency\n      __typename\n    }\n    __typename\n  }\n  isCashback {\n    percentage\n    __typename\n  }\n  isTradeIn\n  isOS\n  isPowerMerchant\n  isWishlist\n  isCOD\n  preorder {\n    duration\n    timeUnit\n    isActive\n    preorderInDays\n    __typename\n  }\n  __typename\n}\n\nquery PDPGetLayoutQuery($shopDomain: String, $productKey: String, $layoutID: String, $apiVersion: Float, $userLocation: pdpUserLocation, $extParam: String, $tokonow: pdpTokoNow, $deviceID: String) {\n  pdpGetLayout(shopDomain: $shopDomain, productKey: $productKey, layoutID: $layoutID, apiVersion: $apiVersion, userLocation: $userLocation, extParam: $extParam, tokonow: $tokonow, deviceID: $deviceID) {\n    name\n    components {\n      name\n      type\n      position\n      data {\n        ...ProductHighlight\n        __typename\n      }\n      __typename\n    }\n    __typename\n  }\n}";
-const AKAMAI_HEADER: &str = "pdpGetLayout";
-const USER_AGENT_VALUE: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.36";
-
-fn main() {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-
-    let args = Args::parse();
-    assert!(
-        !(args.mqtt_password.is_some() && args.mqtt_username.is_none()),
-        "MQTT Broker password is provided without any username. Aborting..."
-    );
-    if args.mqtt_username.is_some() && args.mqtt_password.is_none() {
-        warn!("MQTT Broker username is provided without password. Continuing...");
-    }
+mod breaker;
+mod calendar;
+mod cli;
+mod history;
+mod import;
+mod init;
+mod share;
+mod sink;
+mod status;
+mod summary;
+mod tui;
 
-    // Initialize HTTP & MQTT client
+/// Product hash tagged onto every log line once known, so output of staggered runs can be told apart
+static LOG_CONTEXT: Mutex<Option<String>> = Mutex::new(None);
 
-    let http_client = Client::builder()
-        .use_rustls_tls()
-        .user_agent(USER_AGENT_VALUE)
-        .danger_accept_invalid_certs(true) // Cringe
-        .timeout(Duration::from_secs(10))
-        .build()
-        .unwrap();
+/// Products whose topics were cleared, for the summary of `delete --all-from-config`
+static DELETED: AtomicUsize = AtomicUsize::new(0);
 
-    let mut mqtt_opts = MqttOptions::new(
-        format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-        args.mqtt_server,
-        args.mqtt_port,
-    );
+/// Significant words of the product name kept in entity IDs
+const SLUG_WORDS: usize = 4;
 
-    if args.mqtt_username.is_some() {
-        info!(target: "mqtt", "Using provided credentials");
-        mqtt_opts.set_credentials(
-            args.mqtt_username.unwrap(),
-            args.mqtt_password.unwrap_or(String::new()),
-        );
+/// Tags the following log lines with a product, or stops tagging them
+fn set_log_context(product: Option<&str>) {
+    if let Ok(mut context) = LOG_CONTEXT.lock() {
+        *context = product.map(str::to_string);
     }
-    mqtt_opts.set_keep_alive(Duration::from_secs(10));
-
-    let (mqtt_client, mut mqtt_connection) = rumqttc::Client::new(mqtt_opts, 2);
-
-    let mqtt_thread = std::thread::Builder::new()
-            .name("MQTTEventLoop".to_string())
-            .spawn(move || {
-                info!(target: "mqtt", "MQTT client running");
-                for notification in mqtt_connection.iter() {
-                    match notification {
-                        Ok(_) => {
-                            debug!(target: "mqtt", "Message = {:?}", notification);
-                        }
-                        Err(rumqttc::ConnectionError::MqttState(rumqttc::StateError::Io(e))) => {
-                            if e.kind() == std::io::ErrorKind::ConnectionAborted {
-                                info!(target: "mqtt", "All MQTT message has been pushed. Stopping gracefully...");
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            error!(target: "mqtt", "Unknown error - {e:?}");
-                        }
-                    }
-                }
-            })
-            .expect("Unable to spawn MQTT sender thread");
+}
 
-    // Continue processing data
+/// `env_logger`'s default format with the product hash added after the target, or one JSON object
+/// per line with `--log-format json`
+fn init_logger(args: &Args) -> Result<(), TrackerError> {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    // Flags take over the default level, module directives from RUST_LOG still apply
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => Some(LevelFilter::Error),
+        (false, 0) => None,
+        (false, 1) => Some(LevelFilter::Debug),
+        (false, _) => Some(LevelFilter::Trace),
+    };
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    if let Some(path) = &args.log_file {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(TrackerError::io(format!(
+                "Unable to open log file {}",
+                path.display()
+            )))?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    if args.no_color || args.log_file.is_some() || args.log_format == LogFormat::Json {
+        builder.write_style(env_logger::WriteStyle::Never);
+    }
+    if args.log_format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let product = LOG_CONTEXT.lock().ok().and_then(|c| c.clone());
+            let line = json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "product_hash": product,
+            });
+            writeln!(buf, "{line}")
+        });
+    } else {
+        builder.format(|buf, record| {
+            write!(buf, "[{} ", buf.timestamp())?;
+            #[cfg(feature = "color")]
+            {
+                let level_style = buf.default_level_style(record.level());
+                write!(buf, "{level_style}{:<5}{level_style:#}", record.level())?;
+            }
+            #[cfg(not(feature = "color"))]
+            write!(buf, "{:<5}", record.level())?;
+            write!(buf, " {}", record.target())?;
+            if let Some(product) = LOG_CONTEXT.lock().ok().and_then(|c| c.clone()) {
+                write!(buf, " {product}")?;
+            }
+            writeln!(buf, "] {}", record.args())
+        });
+    }
+    builder.init();
+    Ok(())
+}
 
-    let url = match reqwest::Url::parse(&args.url) {
-        Ok(a) => a,
+fn main() -> ExitCode {
+    // Parsed before the runtime starts its threads, loading the .env file changes the environment
+    let runs = cli::parse();
+    if let Err(e) = init_logger(&runs[0]) {
+        eprintln!("{e}");
+        return ExitCode::from(e.exit_code());
+    }
+    match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime.block_on(run_all(runs)),
         Err(e) => {
-            error!("Unable to parse URL - {e}");
-            return;
+            let e = TrackerError::io("Unable to start the async runtime")(e);
+            error!("{e}");
+            ExitCode::from(e.exit_code())
         }
-    };
+    }
+}
+
+/// Every product of the config file gets its run, the first failure decides the exit code
+async fn run_all(runs: Vec<Args>) -> ExitCode {
+    let deleting = runs.len() > 1 && runs.iter().all(|args| args.unretain);
+    let total = runs.len();
+    let (quiet, no_color) = (runs[0].quiet, runs[0].no_color);
+
+    let mut failure = None;
+    let mut failed = Vec::new();
+    for args in runs {
+        set_log_context(None);
+        let url = args.url.clone().unwrap_or_default();
+        if let Err(e) = run(args).await {
+            error!("{e}");
+            failure.get_or_insert_with(|| e.exit_code());
+            failed.push(url);
+        }
+    }
+
+    if deleting && !quiet {
+        set_log_context(None);
+        let mut summary = Summary::new(summary::use_color(no_color));
+        summary.row(
+            "Deleted",
+            format!("{} of {total} products", DELETED.load(Ordering::Relaxed)),
+        );
+        if !failed.is_empty() {
+            summary.row("Failed", failed.join(", "));
+        }
+        summary.print();
+    }
+    failure.map_or(ExitCode::SUCCESS, ExitCode::from)
+}
 
-    if url
-        .host_str()
-        .is_none_or(|u| u != "tokopedia.com" && u != "www.tokopedia.com")
+async fn run(args: Args) -> Result<(), TrackerError> {
+    if let Some(Action::Completions { shell }) = args.action {
+        cli::print_completions(shell);
+        return Ok(());
+    }
+    if matches!(args.action, Some(Action::Man)) {
+        return cli::print_man();
+    }
+    if let Some(Action::VerifyHistory { file }) = &args.action {
+        let key = args.history_key.as_deref().ok_or_else(|| {
+            TrackerError::Usage("verify-history needs the --history-key".to_string())
+        })?;
+        return history::verify(file, &history::Signer::load(key)?);
+    }
+    if let Some(Action::CompactHistory {
+        file,
+        keep_days,
+        daily,
+    }) = &args.action
     {
-        error!("Parsed URL host: {:?}", url.host_str());
-        panic!("Wrong URL - This tool currently only supports tokopedia.com urls")
+        // Rows that don't match would otherwise end up in the daily averages
+        if let Some(key) = &args.history_key {
+            history::verify(file, &history::Signer::load(key)?)?;
+        }
+        let daily = daily
+            .clone()
+            .unwrap_or_else(|| file.with_extension("daily.csv"));
+        return history::compact(file, &daily, *keep_days);
     }
-    let Some(mut path_segment) = url.path_segments() else {
-        panic!("Wrong URL format - Seems like you've pasted in a base URL")
-    };
-    let Some(shop_domain) = path_segment.next() else {
-        panic!("Wrong URL format - Shop domain is empty. Did you copy the right URL?");
-    };
-    let Some(product_key) = path_segment.next() else {
-        panic!("Wrong URL format - Product key is empty. Did you copy a product URL?")
+    if let Some(Action::Init { path }) = &args.action {
+        let path = path.clone();
+        return init::run(args, &path).await;
+    }
+    if let Some(Action::Check { url }) = &args.action {
+        return match (url, &args.config) {
+            (Some(url), _) => check(&args, url).await,
+            (None, Some(path)) => check_config(&args, path).await,
+            (None, None) => Err(TrackerError::Usage(
+                "check needs a URL, or a --config file to check".to_string(),
+            )),
+        };
+    }
+    if let Some(Action::Import { file, track: false }) = &args.action {
+        return import::to_config(&args, file);
+    }
+    if args.sink != Sink::Mqtt {
+        return scrape(&args).await;
+    }
+
+    // Deleting reads what to delete from the broker, even in a dry run
+    let publisher = if args.dry_run && !args.unretain {
+        info!("Dry run, printing what would be published instead of connecting to the broker");
+        Publisher::dry_run(print_dry_run)
+    } else if args.print_discovery {
+        Publisher::dry_run(print_retained)
+    } else if args.fail_fast {
+        Publisher::connect_fail_fast(args.mqtt_options()?)
+    } else {
+        Publisher::connect(args.mqtt_options()?)
     };
+    publisher.wait_connected().await?;
+    let discovery_prefix = args.ha_mqtt_discovery_topic.as_str();
+
+    if matches!(args.action, Some(Action::List)) {
+        return list(publisher, discovery_prefix).await;
+    }
+    if let Some(Action::Audit { fix, yes }) = args.action {
+        return audit(publisher, &args, fix, yes).await;
+    }
+    if let Some(Action::Import { file, track: true }) = &args.action {
+        return import::track(publisher, &args, file).await;
+    }
+    if matches!(args.action, Some(Action::Adopt)) {
+        return import::adopt(publisher, &args).await;
+    }
+    if matches!(args.action, Some(Action::Tui)) {
+        return tui::run(publisher, args).await;
+    }
+    if let Some(path) = &args.export_state {
+        return export_state(publisher, discovery_prefix, path).await;
+    }
+    if let Some(path) = &args.export_ics {
+        return export_ics(publisher, discovery_prefix, path).await;
+    }
+    if args.watch {
+        return watch(publisher, &args).await;
+    }
+    if let Some(path) = &args.import_state {
+        return import_state(publisher, discovery_prefix, path).await;
+    }
+
+    let url = args.url.as_deref().unwrap_or_default();
+    if args.unretain && is_product_hash(url) {
+        set_log_context(Some(url));
+        return delete(publisher, &args, url, Deletion::Requested).await;
+    }
+
+    let source = args.price_source()?;
+    let target = source.parse_url(url)?;
 
-    info!("Parsed shop domain: {shop_domain}");
-    info!("Parsed product key: {product_key}");
+    info!("Parsed shop domain: {}", target.shop);
+    info!("Parsed product key: {}", target.key);
+    if let Some(context) = &target.context {
+        info!("Parsed listing context: {context}");
+    }
 
-    let mut hasher = Blake2sVar::new(4).unwrap();
-    hasher.write_all(shop_domain.as_bytes()).unwrap();
-    hasher.write_all(product_key.as_bytes()).unwrap();
-    let product_hash = hasher.finalize_boxed();
-    let product_hash = format!("{:x}", HexSlice(&product_hash));
+    let product_hash = ha::product_hash(&target.shop, &target.key);
     info!("HA Object hash: {product_hash}");
+    set_log_context(Some(&product_hash));
 
-    // TODO: Split this
-    // If only unretain, special handling
     if args.unretain {
-        warn!(
-            "DELETE FLAG IS SET - Deleting Home Assistant device and its data from MQTT in 10 seconds..."
+        return delete(publisher, &args, &product_hash, Deletion::Requested).await;
+    }
+    if let Some(until) = args.track_until
+        && Utc::now().date_naive() > until
+    {
+        info!("Tracking ended on {until}, deleting the product instead");
+        return delete(publisher, &args, &product_hash, Deletion::Expired).await;
+    }
+
+    let mut status = RunStatus::new();
+    let tracked = track(
+        &publisher,
+        &args,
+        &source,
+        &target,
+        &product_hash,
+        &mut status,
+    )
+    .await;
+    // --diff doesn't publish anything, including how it went
+    if !args.diff {
+        status
+            .report(&publisher, &product_hash, tracked.as_ref().err())
+            .await;
+    }
+    match tracked {
+        // Nothing left to flush through a broken connection
+        Err(e) if e.is_mqtt() => Err(e),
+        Err(e) => {
+            if let Err(disconnect_error) = publisher.disconnect().await {
+                warn!("{disconnect_error}");
+            }
+            Err(e)
+        }
+        Ok(()) => match publisher.disconnect().await {
+            Err(e @ TrackerError::MqttShutdown(_)) if args.lenient => {
+                warn!("{e}");
+                Ok(())
+            }
+            disconnected => disconnected,
+        },
+    }
+}
+
+/// Fetches a product & prints what tracking it would publish, without connecting to the broker
+async fn check(args: &Args, url: &str) -> Result<(), TrackerError> {
+    let source = args.price_source()?;
+    let target = source.parse_url(url)?;
+    let product_hash = ha::product_hash(&target.shop, &target.key);
+    set_log_context(Some(&product_hash));
+
+    let product = source.fetch(&target).await?;
+    dump_raw(args, &product.raw)?;
+    let price = args
+        .price_rounding
+        .map_or(product.price, |step| round_price(product.price, step));
+
+    let mut summary = Summary::new(summary::use_color(args.no_color));
+    summary.row("Product", display_name(args, &product.name));
+    summary.row("HA hash", &product_hash);
+    summary.row("Price", summary::format_idr(price));
+    summary.row("Stock", product.stock);
+    summary.row(
+        "Campaign",
+        product
+            .campaign
+            .as_ref()
+            .map_or("None", |campaign| campaign.name.as_str()),
+    );
+    summary.row(
+        "Category",
+        product.category_path.as_deref().unwrap_or("Unknown"),
+    );
+    for sensor in &args.extra_sensors {
+        if let Some(value) = sensor.extract(&product.raw) {
+            summary.row("Extra", format!("{}: {value}", sensor.key()));
+        }
+    }
+    summary.row(
+        "Schema drift",
+        if product.missing_fields.is_empty() {
+            "None".to_string()
+        } else {
+            product.missing_fields.join(", ")
+        },
+    );
+    summary.print();
+    Ok(())
+}
+
+/// Prints the hash, price & name of every product tracked on the broker
+async fn list(publisher: Publisher, discovery_prefix: &str) -> Result<(), TrackerError> {
+    let retained = publisher
+        .read_retained(&state::topic_filters(discovery_prefix))
+        .await?;
+    let state = state::from_retained(retained, discovery_prefix);
+    let products = state["products"].as_object().into_iter().flatten();
+    for (hash, product) in products {
+        let values = &product["values"];
+        let price = values["price"]
+            .as_str()
+            .and_then(|price| price.parse().ok())
+            .map_or_else(|| "-".to_string(), summary::format_idr);
+        let paused = if values["tracking"] == "OFF" {
+            " (paused)"
+        } else {
+            ""
+        };
+        println!(
+            "{hash}  {price:>16}  {}{paused}",
+            values["name"].as_str().unwrap_or("(unnamed)")
         );
-        std::thread::sleep(Duration::from_secs(10));
+    }
 
-        warn!("Delete commencing...");
-        mqtt_client
-            .publish(
-                format!(
-                    "{}/sensor/tkpd-{product_hash}/name/config",
-                    args.ha_mqtt_discovery_topic
-                ),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete HA Product Name Config");
-        mqtt_client
-            .publish(
-                format!(
-                    "{}/sensor/tkpd-{product_hash}/price/config",
-                    args.ha_mqtt_discovery_topic
-                ),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete HA Product Price Config");
-        mqtt_client
-            .publish(
-                format!(
-                    "{}/sensor/tkpd-{product_hash}/stock/config",
-                    args.ha_mqtt_discovery_topic
-                ),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete HA Product Stock Config");
-        mqtt_client
-            .publish(
-                format!(
-                    "{}/sensor/tkpd-{product_hash}/updated-at/config",
-                    args.ha_mqtt_discovery_topic
-                ),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete HA updated at Config");
-        mqtt_client
-            .publish(
-                format!(
-                    "{}/sensor/tkpd-{product_hash}/scraper-version/config",
-                    args.ha_mqtt_discovery_topic
-                ),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete HA scraper version Config");
-        mqtt_client
-            .publish(
-                format!("tkpdprice/{product_hash}/name"),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete item name value");
-        mqtt_client
-            .publish(
-                format!("tkpdprice/{product_hash}/price"),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
-            )
-            .expect("Unable to delete item price value");
-        mqtt_client
-            .publish(
-                format!("tkpdprice/{product_hash}/stock"),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
+    publisher.disconnect().await?;
+    Ok(())
+}
+
+/// Checks the options & URL of every product of the config file, then the broker connection.
+/// Nothing is fetched or published
+async fn check_config(args: &Args, path: &Path) -> Result<(), TrackerError> {
+    let products = cli::check_config(path).map_err(TrackerError::Usage)?;
+    let mut invalid = 0;
+    for (url, run_args) in &products {
+        let target = run_args
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|run_args| {
+                run_args
+                    .price_source()
+                    .and_then(|source| source.parse_url(url))
+                    .map_err(|e| e.to_string())
+            });
+        match target {
+            Ok(target) => println!(
+                "ok      {}  https://tokopedia.com/{}/{}",
+                ha::product_hash(&target.shop, &target.key),
+                target.shop,
+                target.key
+            ),
+            Err(e) => {
+                invalid += 1;
+                println!("failed  -         {url} - {e}");
+            }
+        }
+    }
+
+    let broker = async {
+        let publisher = Publisher::connect_fail_fast(args.mqtt_options()?);
+        publisher.wait_connected().await?;
+        publisher.disconnect().await
+    }
+    .await;
+    let address = format!("{}:{}", args.mqtt_server, args.mqtt_port);
+    match &broker {
+        Ok(()) => println!("ok      broker    {address}"),
+        Err(e) => println!("failed  broker    {address} - {e}"),
+    }
+    broker?;
+    if invalid > 0 {
+        return Err(TrackerError::Usage(format!(
+            "{invalid} of the {} products in {} are invalid",
+            products.len(),
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Reports the config file's products that were never published and the products on the broker
+/// it doesn't list, deleting the latter with `fix`
+async fn audit(
+    publisher: Publisher,
+    args: &Args,
+    fix: bool,
+    yes: bool,
+) -> Result<(), TrackerError> {
+    let path = args.config.as_deref().ok_or_else(|| {
+        TrackerError::Usage("audit compares the products of the --config file".to_string())
+    })?;
+    let urls = cli::product_urls(path)?;
+    if urls.is_empty() {
+        return Err(TrackerError::Usage(format!(
+            "{} has no [[products]] to audit",
+            path.display()
+        )));
+    }
+    let source = args.price_source()?;
+    let discovery_prefix = args.ha_mqtt_discovery_topic.as_str();
+    let retained = publisher
+        .read_retained(&state::topic_filters(discovery_prefix))
+        .await?;
+    let state = state::from_retained(retained, discovery_prefix);
+    let on_broker = state["products"].as_object().cloned().unwrap_or_default();
+
+    let today = Utc::now().date_naive();
+    let mut configured = HashSet::new();
+    let mut expired = HashSet::new();
+    for (url, track_until) in &urls {
+        match source.parse_url(url) {
+            Ok(target) => {
+                let product_hash = ha::product_hash(&target.shop, &target.key);
+                if let Some(until) = track_until.filter(|until| today > *until) {
+                    println!("Expired          {product_hash}  {url} - tracking ended on {until}");
+                    expired.insert(product_hash.clone());
+                } else if !on_broker.contains_key(&product_hash) {
+                    println!("Never published  {product_hash}  {url}");
+                }
+                configured.insert(product_hash);
+            }
+            Err(e) => println!("Invalid URL      -         {url} - {e}"),
+        }
+    }
+    let orphans: Vec<(&String, &str)> = on_broker
+        .iter()
+        .filter(|(product_hash, _)| !configured.contains(*product_hash))
+        .map(|(product_hash, product)| {
+            let name = product["values"]["name"].as_str();
+            (product_hash, name.unwrap_or("(unnamed)"))
+        })
+        .collect();
+    for (product_hash, name) in &orphans {
+        println!("Not configured   {product_hash}  {name}");
+    }
+    let never_published = configured
+        .iter()
+        .filter(|product_hash| {
+            !on_broker.contains_key(*product_hash) && !expired.contains(*product_hash)
+        })
+        .count();
+    info!(
+        "{} configured, {} on the broker: {never_published} never published, {} not configured",
+        urls.len(),
+        on_broker.len(),
+        orphans.len()
+    );
+    if !expired.is_empty() {
+        info!(
+            "{} products are past their track-until date, runs of the config file delete them \
+             instead of tracking them. Remove them from {}",
+            expired.len(),
+            path.display()
+        );
+    }
+    if never_published > 0 {
+        info!(
+            "Products that were never published are published by the next run of the config file, \
+             if they stay missing that run fails for them"
+        );
+    }
+
+    if fix {
+        for (product_hash, name) in orphans {
+            let retained = publisher
+                .read_retained(&state::product_topic_filters(
+                    discovery_prefix,
+                    product_hash,
+                ))
+                .await?;
+            if !yes && !confirm_delete(name, product_hash, retained.len())? {
+                continue;
+            }
+            clear_product(&publisher, &retained, discovery_prefix, product_hash).await?;
+            info!("Deleted {product_hash}, backed up to the working directory");
+        }
+    }
+
+    publisher.disconnect().await
+}
+
+/// Scrapes the product into a sink other than MQTT, without connecting to the broker
+async fn scrape(args: &Args) -> Result<(), TrackerError> {
+    let broker_flags = [
+        ("--delete", args.unretain),
+        ("--diff", args.diff),
+        ("--export-state", args.export_state.is_some()),
+        ("--import-state", args.import_state.is_some()),
+        ("--export-ics", args.export_ics.is_some()),
+        ("--watch", args.watch),
+    ];
+    if let Some((flag, _)) = broker_flags.iter().find(|(_, set)| *set) {
+        return Err(TrackerError::Usage(format!(
+            "{flag} works on the broker, which the {} sink doesn't use",
+            args.sink.as_str()
+        )));
+    }
+
+    let source = args.price_source()?;
+    let target = source.parse_url(args.url.as_deref().unwrap_or_default())?;
+    let product_hash = ha::product_hash(&target.shop, &target.key);
+    set_log_context(Some(&product_hash));
+
+    let product = source.fetch(&target).await?;
+    dump_raw(args, &product.raw)?;
+    let display_name = display_name(args, &product.name);
+    let price = args
+        .price_rounding
+        .map_or(product.price, |step| round_price(product.price, step));
+    let extras = args
+        .extra_sensors
+        .iter()
+        .filter_map(|sensor| Some((sensor.key(), sensor.extract(&product.raw)?)))
+        .collect();
+    let mut record = sink::record(
+        &target,
+        &product_hash,
+        &display_name,
+        price,
+        &product,
+        extras,
+    );
+    record["group"] = json!(args.group);
+    args.sink.write(args.output.as_deref(), &record)?;
+
+    if let Some(path) = &args.history_csv {
+        let signer = args
+            .history_key
+            .as_deref()
+            .map(history::Signer::load)
+            .transpose()?;
+        history::append_csv(
+            path,
+            signer.as_ref(),
+            Utc::now(),
+            &product_hash,
+            &display_name,
+            price,
+            Some(product.stock),
+        )?;
+    }
+    info!("Product written to the {} sink", args.sink.as_str());
+    Ok(())
+}
+
+/// Writes every tracked product's retained topics to a file
+async fn export_state(
+    publisher: Publisher,
+    discovery_prefix: &str,
+    path: &Path,
+) -> Result<(), TrackerError> {
+    let retained = publisher
+        .read_retained(&state::topic_filters(discovery_prefix))
+        .await?;
+    let state = state::from_retained(retained, discovery_prefix);
+    info!(
+        "Exporting {} tracked products",
+        state["products"]
+            .as_object()
+            .map_or(0, serde_json::Map::len)
+    );
+    std::fs::write(path, serde_json::to_string_pretty(&state)?).map_err(TrackerError::io(
+        format!("Unable to write {}", path.display()),
+    ))?;
+
+    publisher.disconnect().await?;
+    info!("Tracker state exported to {}", path.display());
+    Ok(())
+}
+
+/// Writes upcoming campaign end times of every tracked product to an iCalendar file
+async fn export_ics(
+    publisher: Publisher,
+    discovery_prefix: &str,
+    path: &Path,
+) -> Result<(), TrackerError> {
+    let retained = publisher
+        .read_retained(&state::topic_filters(discovery_prefix))
+        .await?;
+    let state = state::from_retained(retained, discovery_prefix);
+    std::fs::write(path, calendar::from_state(&state)).map_err(TrackerError::io(format!(
+        "Unable to write {}",
+        path.display()
+    )))?;
+
+    publisher.disconnect().await?;
+    info!("Campaign calendar written to {}", path.display());
+    Ok(())
+}
+
+/// Prints published values until the connection closes
+async fn watch(publisher: Publisher, args: &Args) -> Result<(), TrackerError> {
+    let mut filters = vec!["tkpdprice/#".to_string()];
+    if let Some(prefix) = &args.share {
+        filters.push(share::topic(prefix, "+"));
+    }
+    let mut incoming = publisher.subscribe(&filters).await?;
+    info!("Watching tracked products, press Ctrl+C to stop");
+    let mut values = HashMap::new();
+    loop {
+        match incoming.recv().await {
+            Ok(publish) => match args
+                .share
+                .as_deref()
+                .and_then(|prefix| share::parse(prefix, &publish))
+            {
+                Some(observation) => collect_shared(args, &values, &observation)?,
+                None => print_watched(&mut values, &publish),
+            },
+            Err(RecvError::Lagged(skipped)) => warn!("Missed {skipped} messages"),
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    publisher.disconnect().await?;
+    Ok(())
+}
+
+/// Re-publishes retained topics from a file written by `--export-state`
+async fn import_state(
+    publisher: Publisher,
+    discovery_prefix: &str,
+    path: &Path,
+) -> Result<(), TrackerError> {
+    let state: Value = serde_json::from_str(&std::fs::read_to_string(path).map_err(
+        TrackerError::io(format!("Unable to read {}", path.display())),
+    )?)?;
+    let topics = state::to_topics(&state, discovery_prefix).map_err(TrackerError::State)?;
+
+    info!("Importing {} retained topics", topics.len());
+    for (topic, payload) in topics {
+        publisher
+            .client()
+            .publish(&topic, QoS::AtLeastOnce, true, payload)
+            .await?;
+    }
+
+    publisher.disconnect().await?;
+    info!("Tracker state imported from {}", path.display());
+    Ok(())
+}
+
+/// Why a product is deleted
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Deletion {
+    /// Asked for by the user, who confirms it unless `--yes`
+    Requested,
+    /// Its `--track-until` date passed, so nothing is asked and a product already deleted is skipped
+    Expired,
+}
+
+/// Removes every retained topic of a product after a backup, asking first when requested without `--yes`
+async fn delete(
+    publisher: Publisher,
+    args: &Args,
+    product_hash: &str,
+    deletion: Deletion,
+) -> Result<(), TrackerError> {
+    // Whatever is retained is what gets deleted, including extra sensors & keys from older versions
+    let retained = publisher
+        .read_retained(&state::product_topic_filters(
+            &args.ha_mqtt_discovery_topic,
+            product_hash,
+        ))
+        .await?;
+
+    if args.dry_run || retained.is_empty() {
+        for topic in retained.keys() {
+            println!("{topic}");
+        }
+        if retained.is_empty() && deletion == Deletion::Expired {
+            info!("{product_hash} was already deleted, skipping it");
+        } else if retained.is_empty() {
+            warn!("Nothing is retained for {product_hash}. Nothing to delete");
+        } else {
+            info!("Dry run - {} topics would be deleted", retained.len());
+        }
+        publisher.disconnect().await?;
+        return Ok(());
+    }
+
+    let device_name = retained
+        .get(&format!("tkpdprice/{product_hash}/name"))
+        .map_or("(unnamed)", String::as_str);
+    if deletion == Deletion::Requested
+        && !args.yes
+        && !confirm_delete(device_name, product_hash, retained.len())?
+    {
+        info!("Delete cancelled");
+        publisher.disconnect().await?;
+        return Ok(());
+    }
+
+    warn!("Delete commencing...");
+    clear_product(
+        &publisher,
+        &retained,
+        &args.ha_mqtt_discovery_topic,
+        product_hash,
+    )
+    .await?;
+    publisher.disconnect().await?;
+    DELETED.fetch_add(1, Ordering::Relaxed);
+
+    info!("HA Device and its data has been deleted successfully. Thanks for using me!");
+    Ok(())
+}
+
+/// Scrapes & publishes a product the way a run tracking it does, reporting how it went, for the
+/// modes tracking several products over one connection
+async fn track_url(publisher: &Publisher, args: &Args, url: &str) -> Result<(), TrackerError> {
+    let source = args.price_source()?;
+    let target = source.parse_url(url)?;
+    let product_hash = ha::product_hash(&target.shop, &target.key);
+    set_log_context(Some(&product_hash));
+    let mut status = RunStatus::new();
+    let tracked = track(
+        publisher,
+        args,
+        &source,
+        &target,
+        &product_hash,
+        &mut status,
+    )
+    .await;
+    status
+        .report(publisher, &product_hash, tracked.as_ref().err())
+        .await;
+    tracked
+}
+
+/// Backs up a product's retained topics, then clears them
+async fn clear_product(
+    publisher: &Publisher,
+    retained: &BTreeMap<String, String>,
+    discovery_prefix: &str,
+    product_hash: &str,
+) -> Result<(), TrackerError> {
+    backup_product(retained, discovery_prefix, product_hash)?;
+    for topic in retained.keys() {
+        publisher
+            .client()
+            .publish(topic, QoS::AtLeastOnce, true, [])
+            .await?;
+    }
+    Ok(())
+}
+
+/// Scrapes the product and publishes its device, states & triggers
+async fn track(
+    publisher: &Publisher,
+    args: &Args,
+    source: &(impl PriceSource + Sync),
+    target: &Target,
+    product_hash: &str,
+    run_status: &mut RunStatus,
+) -> Result<(), TrackerError> {
+    let previous = publisher.read_retained_values(product_hash).await?;
+
+    if !args.diff && previous.get("tracking").is_some_and(|t| t == "OFF") {
+        info!("Tracking is paused from Home Assistant. Skipping scrape...");
+        publisher
+            .publish_states(
+                product_hash,
+                vec![("availability".to_string(), "offline".to_string())],
             )
-            .expect("Unable to delete item stock value");
-        mqtt_client
-            .publish(
-                format!("tkpdprice/{product_hash}/updated-at"),
-                rumqttc::QoS::AtLeastOnce,
-                true,
-                [],
+            .await?;
+        run_status.enter(Stage::Paused);
+        return Ok(());
+    }
+
+    let last_update = previous
+        .get("updated-at")
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+        .map(|at| at.with_timezone(&Utc));
+    if let (Some(minutes), Some(last_update)) = (args.min_interval, last_update)
+        && !args.diff
+        && Utc::now() - last_update < TimeDelta::minutes(minutes.into())
+    {
+        info!("Updated at {last_update}, less than {minutes} minutes ago. Skipping scrape...");
+        run_status.enter(Stage::Skipped);
+        return Ok(());
+    }
+
+    // A URL fixed from HA is fetched instead, the device stays the one of the URL the run was given
+    let edited_target = previous
+        .get("url")
+        .and_then(|url| match source.parse_url(url) {
+            Ok(edited) => Some(edited),
+            Err(e) => {
+                warn!("Ignoring the product URL set from Home Assistant - {e}");
+                None
+            }
+        })
+        .filter(|edited| edited.shop != target.shop || edited.key != target.key);
+    if let Some(edited) = &edited_target {
+        info!(
+            "Fetching {}/{} instead, as set from Home Assistant",
+            edited.shop, edited.key
+        );
+    }
+    let fetch_target = edited_target.as_ref().unwrap_or(target);
+
+    // A diff publishes nothing, so it neither waits for the breaker nor counts towards it
+    let mut breaker = match args.breaker_failures.filter(|_| !args.diff) {
+        Some(_) => Some(Breaker::read(publisher).await?),
+        None => None,
+    };
+    if let Some(until) = breaker.as_ref().and_then(Breaker::open_until) {
+        warn!("Requests to Tokopedia are paused until {until}. Skipping scrape...");
+        run_status.enter(Stage::Skipped);
+        return Ok(());
+    }
+
+    run_status.enter(Stage::Fetch);
+    let fetched = source.fetch(fetch_target).await;
+    if let (Some(breaker), Some(max_failures)) = (&mut breaker, args.breaker_failures) {
+        breaker
+            .record(
+                publisher,
+                &args.ha_mqtt_discovery_topic,
+                fetched.as_ref().err(),
+                max_failures,
+                TimeDelta::minutes(args.breaker_cooldown.into()),
             )
-            .expect("Unable to delete last updated timestamp value");
+            .await?;
+    }
+    let product = fetched?;
+    let data = &product.raw;
+    run_status.enter(Stage::Parse);
+    run_status.checked_schema(&product.missing_fields);
+
+    if args.diff {
+        print_diff(&previous, args, &product);
+        run_status.enter(Stage::Done);
+        return Ok(());
+    }
+
+    let config_hash = config_hash(args);
+    let instance_id = args.instance_id.clone().unwrap_or_else(default_instance_id);
+    check_ownership(&previous, &instance_id, &config_hash);
+
+    let dump_to_stdout = dump_raw(args, data)?;
+
+    let product_campaign = product
+        .campaign
+        .as_ref()
+        .map_or("None", |campaign| campaign.name.as_str());
+    let campaign_end = product
+        .campaign
+        .as_ref()
+        .and_then(|campaign| campaign.ends_at);
+
+    info!("Product name: {}", product.name);
+    let display_name = display_name(args, &product.name);
+    if display_name != product.name {
+        info!("Display name: {display_name}");
+    }
+
+    info!(
+        "Category: {}",
+        product.category_path.as_deref().unwrap_or("Unknown")
+    );
+    info!("Brand: {}", product.brand.as_deref().unwrap_or("Unknown"));
+
+    let previous_condition = previous
+        .get("attributes")
+        .and_then(|a| serde_json::from_str::<Value>(a).ok())
+        .and_then(|a| a["condition"].as_str().map(str::to_string));
+    let condition_changed = previous_condition.is_some() && previous_condition != product.condition;
+    info!(
+        "Condition: {}",
+        product.condition.as_deref().unwrap_or("Unknown")
+    );
+    if condition_changed {
+        warn!(
+            "Listing condition changed from {} to {}",
+            previous_condition.as_deref().unwrap_or("unknown"),
+            product.condition.as_deref().unwrap_or("unknown")
+        );
+    }
+    info!(
+        "Weight: {} g",
+        product
+            .weight
+            .map_or_else(|| "Unknown".to_string(), |w| w.to_string())
+    );
+    info!("Price: Rp. {}", product.price);
+    let published_price = args
+        .price_rounding
+        .map_or(product.price, |step| round_price(product.price, step));
+    if published_price != product.price {
+        info!("Normalized price: Rp. {published_price}");
+    }
+
+    let previous_price = previous.get("price").and_then(|p| p.parse::<i64>().ok());
+    let price_anomaly = detect_price_anomaly(
+        previous_price,
+        published_price,
+        args.anomaly_threshold,
+        args.price_floor,
+    );
+    let anomaly_confirmed = previous
+        .get("pending-price")
+        .is_some_and(|p| *p == published_price.to_string());
+    let drop_unconfirmed = args.confirm_drop.is_some_and(|threshold| {
+        previous_price.is_some_and(|p| p != 0 && percent_change(p, published_price) <= -threshold)
+    }) && {
+        info!(
+            "Large price drop, re-checking in {} seconds before publishing...",
+            args.confirm_delay
+        );
+        tokio::time::sleep(Duration::from_secs(args.confirm_delay)).await;
+        match source.fetch(fetch_target).await {
+            Ok(recheck) if recheck.price == product.price => {
+                info!("Price drop confirmed");
+                false
+            }
+            Ok(recheck) => {
+                warn!(
+                    "Price changed to {} on re-check, not publishing the drop",
+                    recheck.price
+                );
+                true
+            }
+            Err(e) => {
+                warn!("Unable to re-check the price, not publishing the drop - {e}");
+                true
+            }
+        }
+    };
+    let price_held =
+        (price_anomaly.is_some() && args.hold_anomalies && !anomaly_confirmed) || drop_unconfirmed;
+    if let Some(reason) = &price_anomaly {
+        warn!("Price anomaly detected - {reason}");
+        if price_held {
+            warn!("Holding back the new price until the next scrape confirms it");
+        } else if anomaly_confirmed {
+            info!("Anomalous price was confirmed by this scrape, publishing it");
+        }
+    }
+    info!("Stock: {}", product.stock);
+    let stock_remaining = product
+        .campaign
+        .as_ref()
+        .and_then(Campaign::stock_remaining_percent);
+    if let Some(percent) = stock_remaining {
+        info!("Campaign stock remaining: {percent:.0}%");
+    }
+    info!("Campaign: {product_campaign}");
+    if let Some(end) = campaign_end {
+        info!("Campaign ends at {}", end.to_rfc3339());
+    }
+    let upcoming_campaign = product.upcoming_campaign.as_ref();
+    if let Some(upcoming) = upcoming_campaign {
+        info!(
+            "Upcoming campaign: {} from {} at Rp. {}",
+            upcoming.name,
+            upcoming
+                .starts_at
+                .map_or_else(|| "unknown".to_string(), |start| start.to_rfc3339()),
+            upcoming
+                .price
+                .map_or_else(|| "unknown".to_string(), |price| price.to_string())
+        );
+    }
+
+    // Score what HA will show, held back prices don't count towards the lowest price either
+    let shown_price = if price_held {
+        previous_price.unwrap_or(published_price)
+    } else {
+        published_price
+    };
+    let lowest_price = previous
+        .get("lowest-price")
+        .and_then(|p| p.parse::<i64>().ok())
+        .or(previous_price)
+        .map_or(shown_price, |lowest| lowest.min(shown_price));
+    let score = deal_score(
+        shown_price,
+        lowest_price,
+        product.discount_percent(),
+        product.campaign.is_some(),
+    );
+    info!("Deal score: {score} (lowest price Rp. {lowest_price})");
+
+    let slug = match (&args.object_id, previous.get("slug")) {
+        (Some(object_id), _) => object_id.clone(),
+        (None, Some(slug)) => slug.clone(),
+        (None, None) => unique_slug(publisher, product_hash, &display_name).await?,
+    };
+    let discovery_configs = discovery_configs(args, target, product_hash, &display_name, &slug)?;
+    run_status.enter(Stage::Publish);
+    publisher
+        .publish_device(
+            &args.ha_mqtt_discovery_topic,
+            product_hash,
+            &discovery_configs,
+        )
+        .await?;
+
+    // Send data
+    let updated_at = Utc::now();
+    let mut states = vec![
+        ("name".to_string(), display_name.clone()),
+        ("stock".to_string(), product.stock.to_string()),
+        (
+            "stock-delta".to_string(),
+            previous
+                .get("stock")
+                .and_then(|s| s.parse::<i64>().ok())
+                .map_or_else(|| "None".to_string(), |s| (product.stock - s).to_string()),
+        ),
+        ("campaign".to_string(), product_campaign.to_string()),
+        // HA reads "None" as an unknown state
+        (
+            "campaign-ends".to_string(),
+            campaign_end.map_or_else(|| "None".to_string(), |end| end.to_rfc3339()),
+        ),
+        (
+            "campaign-remaining".to_string(),
+            campaign_end.map_or_else(
+                || "None".to_string(),
+                |end| (end - Utc::now()).num_minutes().max(0).to_string(),
+            ),
+        ),
+        (
+            "upcoming-campaign".to_string(),
+            upcoming_campaign
+                .map_or("None", |c| c.name.as_str())
+                .to_string(),
+        ),
+        (
+            "upcoming-campaign-starts".to_string(),
+            upcoming_campaign
+                .and_then(|c| c.starts_at)
+                .map_or_else(|| "None".to_string(), |start| start.to_rfc3339()),
+        ),
+        (
+            "upcoming-campaign-price".to_string(),
+            upcoming_campaign
+                .and_then(|c| c.price)
+                .map_or_else(|| "None".to_string(), |price| price.to_string()),
+        ),
+        (
+            "weight".to_string(),
+            product
+                .weight
+                .map_or_else(|| "None".to_string(), |w| w.to_string()),
+        ),
+        ("lowest-price".to_string(), lowest_price.to_string()),
+        ("deal-score".to_string(), score.to_string()),
+        ("updated-at".to_string(), updated_at.to_rfc3339()),
+        (
+            "scraper-version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ];
+    if args.publish_raw_price {
+        states.push(("raw-price".to_string(), product.price.to_string()));
+    }
+    if args.publish_stock_percent {
+        states.push((
+            "stock-remaining".to_string(),
+            stock_remaining.map_or_else(|| "None".to_string(), |p| format!("{p:.1}")),
+        ));
+    }
+    if !price_held {
+        states.push(("price".to_string(), published_price.to_string()));
+    }
+    states.extend(
+        args.extra_sensors
+            .iter()
+            .filter_map(|sensor| Some((sensor.key(), sensor.extract(data)?))),
+    );
+    if !previous.contains_key("tracking") {
+        states.push(("tracking".to_string(), "ON".to_string()));
+    }
+    if previous.get("slug") != Some(&slug) {
+        states.push(("slug".to_string(), slug));
+    }
+    if !previous.contains_key("url") {
+        states.push((
+            "url".to_string(),
+            format!("https://tokopedia.com/{}/{}", target.shop, target.key),
+        ));
+    }
+    states.push(("availability".to_string(), "online".to_string()));
+    states.push((
+        "attributes".to_string(),
+        json!({
+            "original_name": product.name,
+            "category": product.category,
+            "category_breadcrumb": product.category_path,
+            "brand": product.brand,
+            "dimensions": product.dimensions,
+            "condition": product.condition,
+            "warranty": product.warranty,
+            "group": args.group,
+        })
+        .to_string(),
+    ));
+    publisher.publish_states(product_hash, states).await?;
+
+    let previous_stock = previous.get("stock").and_then(|s| s.parse::<i64>().ok());
+    let fired_triggers = [
+        !price_held
+            && previous_price.is_some_and(|p| {
+                published_price < p
+                    && args
+                        .drop_threshold
+                        .is_none_or(|threshold| -percent_change(p, published_price) >= threshold)
+            }),
+        previous_stock == Some(0) && product.stock > 0,
+        previous
+            .get("campaign")
+            .is_some_and(|c| c == "None" && product.campaign.is_some()),
+        price_anomaly.is_some(),
+        condition_changed,
+    ];
+    // The states are out at this point, what follows only adds to them
+    let extras = async {
+        let mqtt_client = publisher.client();
+        if price_held {
+            mqtt_client
+                .publish(
+                    format!("tkpdprice/{product_hash}/pending-price"),
+                    QoS::AtLeastOnce,
+                    true,
+                    published_price.to_string(),
+                )
+                .await?;
+        } else if previous.contains_key("pending-price") {
+            mqtt_client
+                .publish(
+                    format!("tkpdprice/{product_hash}/pending-price"),
+                    QoS::AtLeastOnce,
+                    true,
+                    [],
+                )
+                .await?;
+        }
+        if let Some(reason) = &price_anomaly {
+            mqtt_client
+                .publish(
+                    format!("tkpdprice/{product_hash}/anomaly"),
+                    QoS::AtLeastOnce,
+                    false,
+                    json!({
+                        "previous_price": previous_price,
+                        "price": published_price,
+                        "reason": reason,
+                        "held": price_held,
+                    })
+                    .to_string(),
+                )
+                .await?;
+        }
+
+        for (key, _) in TRIGGER_KEYS
+            .iter()
+            .zip(fired_triggers)
+            .filter(|(_, fired)| *fired)
+        {
+            info!("Firing {key} trigger");
+            mqtt_client
+                .publish(
+                    format!("tkpdprice/{product_hash}/trigger"),
+                    QoS::AtLeastOnce,
+                    false,
+                    *key,
+                )
+                .await?;
+        }
+
         mqtt_client
             .publish(
-                format!("tkpdprice/{product_hash}/scraper-version"),
-                rumqttc::QoS::AtLeastOnce,
+                format!("tkpdprice/{product_hash}/meta"),
+                QoS::AtLeastOnce,
                 true,
-                [],
+                json!({
+                    "instance_id": instance_id,
+                    "config_hash": config_hash,
+                    "sw_version": env!("CARGO_PKG_VERSION"),
+                })
+                .to_string(),
             )
-            .expect("Unable to delete scraper version value");
-        mqtt_client.disconnect().expect("Unable to disconnect mqtt");
+            .await?;
 
-        mqtt_thread
-            .join()
-            .expect("MQTT Event loop exited abnormally. Messages might not be fully published!");
+        // Held prices are left out, they might be Tokopedia's mistake
+        if let Some(prefix) = args.share.as_deref().filter(|_| !price_held) {
+            debug!("Sharing the price on {prefix}");
+            mqtt_client
+                .publish(
+                    share::topic(prefix, product_hash),
+                    QoS::AtLeastOnce,
+                    false,
+                    share::payload(product_hash, product.price, updated_at),
+                )
+                .await?;
+        }
+        Ok::<(), TrackerError>(())
+    }
+    .await;
+    if let Err(e) = extras {
+        if !args.lenient {
+            return Err(e);
+        }
+        warn!("Some messages of this scrape might not have been published - {e}");
+    }
 
-        info!("HA Device and its data has been deleted successfully. Thanks for using me!");
-        return;
+    if let Some(path) = &args.history_csv {
+        let signer = args
+            .history_key
+            .as_deref()
+            .map(history::Signer::load)
+            .transpose()?;
+        history::append_csv(
+            path,
+            signer.as_ref(),
+            updated_at,
+            product_hash,
+            &display_name,
+            published_price,
+            Some(product.stock),
+        )?;
     }
 
-    let tokopedia_query = json!({
-        "query": GQL_PDP_QUERY,
-        "operationName": GQL_PDP_OPNAME,
-        "variables": {
-            "shopDomain": shop_domain,
-            "productKey": product_key,
-            "apiVersion": 1,
-        }
+    let mut summary = Summary::new(summary::use_color(args.no_color));
+    summary.row("Product", &display_name);
+    summary.change(
+        "Price",
+        previous_price,
+        published_price,
+        true,
+        summary::format_idr,
+    );
+    if price_held {
+        summary.row("", "Held back, previous price kept in HA");
+    }
+    summary.change("Stock", previous_stock, product.stock, false, |s| {
+        s.to_string()
     });
-
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-    headers.insert(HOST, HeaderValue::from_static("gql.tokopedia.com"));
-    headers.insert(
-        REFERER,
-        HeaderValue::from_str(&format!(
-            "https://www.tokopedia.com/{shop_domain}/{product_key}"
-        ))
-        .unwrap(),
+    summary.row("Deal score", format!("{score}/100"));
+    summary.row(
+        "Campaign",
+        campaign_end.map_or_else(
+            || product_campaign.to_string(),
+            |end| format!("{product_campaign} until {}", end.to_rfc3339()),
+        ),
     );
-    headers.insert("x-tkpd-akamai", HeaderValue::from_static(AKAMAI_HEADER));
-
-    info!("Sending Tokopedia API request");
-    let response = http_client
-        .post(TKPD_GQL_ENDPOINT)
-        .headers(headers)
-        .body(tokopedia_query.to_string())
-        .send()
-        .expect("Failed to send request");
-
-    info!("HTTP response received!");
-    let body: Value = response.json().expect("Failed to read response text");
-    trace!("{}", body);
-
-    // Handle Error
-    if let Some(err) = &body.get("errors") {
-        let first_error = err.get(0).expect("Ada error tapi gaada error woi");
-        let message = first_error
-            .get("message")
-            .expect("Woi ada error tapi messagenya gaada goblok ini toped");
-        panic!("Unable to fetch product data - {message}")
-    }
-
-    let component = &body["data"]["pdpGetLayout"]["components"];
-    let Some(data) = component
-        .as_array()
-        .unwrap()
+    let fired: Vec<&str> = TRIGGER_KEYS
         .iter()
-        .find(|c| c.get("name").unwrap() == "product_content")
-        .and_then(|c| c.get("data"))
-        .and_then(|d| d.get(0))
+        .zip(fired_triggers)
+        .filter_map(|(key, fired)| fired.then_some(*key))
+        .collect();
+    if !fired.is_empty() {
+        summary.row("Triggers", fired.join(", "));
+    }
+    // Keep stdout parseable when the raw data or the discovery configs went there
+    match args.output_format {
+        _ if dump_to_stdout || args.print_discovery => {}
+        OutputFormat::Pretty if !args.quiet => summary.print(),
+        OutputFormat::Json => {
+            let mut record = sink::record(
+                target,
+                product_hash,
+                &display_name,
+                published_price,
+                &product,
+                args.extra_sensors
+                    .iter()
+                    .filter_map(|sensor| Some((sensor.key(), sensor.extract(data)?)))
+                    .collect(),
+            );
+            record["updated_at"] = json!(updated_at.to_rfc3339());
+            record["group"] = json!(args.group);
+            record["price_held"] = json!(price_held);
+            record["previous"] = json!({
+                "price": previous_price,
+                "stock": previous_stock,
+                "updated_at": previous.get("updated-at"),
+            });
+            record["triggers"] = json!(fired);
+            println!("{record}");
+        }
+        OutputFormat::Pretty | OutputFormat::None => {}
+    }
+
+    run_status.enter(Stage::Done);
+    info!("Everything looks successful. Exiting...");
+    Ok(())
+}
+
+/// Writes the raw product data where `--dump-raw` asks, telling whether that was stdout
+fn dump_raw(args: &Args, data: &Value) -> Result<bool, TrackerError> {
+    debug!("Product data = {data}");
+    let dump_to_stdout = args
+        .dump_raw
+        .as_ref()
+        .is_some_and(|path| path.as_os_str() == "-");
+    if dump_to_stdout {
+        println!("{}", serde_json::to_string_pretty(data)?);
+    } else if let Some(path) = &args.dump_raw {
+        std::fs::write(path, serde_json::to_string_pretty(data)?).map_err(TrackerError::io(
+            format!("Unable to write {}", path.display()),
+        ))?;
+        info!("Raw product data written to {}", path.display());
+    }
+    Ok(dump_to_stdout)
+}
+
+/// Whether a `--delete` target is an HA object hash rather than a URL
+fn is_product_hash(target: &str) -> bool {
+    target.len() == 8 && target.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Hash of the flags shaping the published device, compared by [`check_ownership`]
+fn config_hash(args: &Args) -> String {
+    let mut config_parts = vec![args.ha_mqtt_discovery_topic.clone()];
+    config_parts.extend(
+        args.extra_sensors
+            .iter()
+            .map(|sensor| format!("{sensor:?}")),
+    );
+    config_parts.extend(
+        args.sensor_overrides
+            .iter()
+            .map(|sensor_override| format!("{sensor_override:?}")),
+    );
+    config_parts.extend(args.group.iter().map(|group| format!("group={group}")));
+    ha::short_hash(&config_parts)
+}
+
+/// The product name used in HA, cleaned up & truncated as the flags ask
+fn display_name(args: &Args, name: &str) -> String {
+    let name = args.name.as_deref().unwrap_or(name);
+    let name = if args.clean_name {
+        name_cleanup::clean(name)
+    } else {
+        name.to_string()
+    };
+    match args.name_max_length {
+        Some(max) => name_cleanup::truncate(&name, max),
+        None => name,
+    }
+}
+
+/// Every discovery config of the product, with the optional sensors the flags ask for
+fn discovery_configs(
+    args: &Args,
+    target: &Target,
+    product_hash: &str,
+    display_name: &str,
+    slug: &str,
+) -> Result<Vec<(String, Value)>, TrackerError> {
+    let mut device_info = ha::device_info(&target.shop, &target.key, product_hash, display_name);
+    if let Some(group) = &args.group {
+        device_info["suggested_area"] = json!(group);
+    }
+    let mut discovery_configs = ha::discovery_configs(product_hash, &device_info);
+    if args.publish_raw_price {
+        discovery_configs.push(ha::raw_price_config(product_hash, &device_info));
+    }
+    if args.publish_stock_percent {
+        discovery_configs.push(ha::stock_percent_config(product_hash, &device_info));
+    }
+    discovery_configs.extend(
+        args.extra_sensors
+            .iter()
+            .map(|sensor| sensor.discovery_config(product_hash, &device_info)),
+    );
+    ha::add_availability(&mut discovery_configs, product_hash);
+    ha::add_entity_ids(&mut discovery_configs, slug);
+    ha::apply_overrides(&mut discovery_configs, &args.sensor_overrides);
+
+    // Catch payload mistakes here, HA silently ignores configs it can't understand
+    for (key, config) in &discovery_configs {
+        discovery_lint::lint_payload(config).map_err(|problems| TrackerError::Discovery {
+            key: key.clone(),
+            problems,
+        })?;
+    }
+
+    Ok(discovery_configs)
+}
+
+/// A slug of the name for entity IDs, numbered when another product already has it. Kept once
+/// published, so entity IDs don't follow later renames
+async fn unique_slug(
+    publisher: &Publisher,
+    product_hash: &str,
+    name: &str,
+) -> Result<String, TrackerError> {
+    let base = name_cleanup::slug(name, SLUG_WORDS);
+    let taken: Vec<String> = publisher
+        .read_retained(&["tkpdprice/+/slug".to_string()])
+        .await?
+        .into_iter()
+        .filter(|(topic, _)| *topic != format!("tkpdprice/{product_hash}/slug"))
+        .map(|(_, slug)| slug)
+        .collect();
+    let mut slug = base.clone();
+    let mut n = 1;
+    while taken.contains(&slug) {
+        n += 1;
+        slug = format!("{base}_{n}");
+    }
+    info!("Entity ID slug: {slug}");
+    Ok(slug)
+}
+
+/// Asks on the terminal whether the device should really be deleted
+fn confirm_delete(
+    device_name: &str,
+    product_hash: &str,
+    topic_count: usize,
+) -> Result<bool, TrackerError> {
+    if !std::io::stdin().is_terminal() {
+        error!(
+            "Refusing to delete without a terminal to confirm on. Pass --yes to skip confirmation"
+        );
+        return Ok(false);
+    }
+
+    eprint!(
+        "Delete \"{device_name}\" ({product_hash}) and its {topic_count} retained topics? [y/N] "
+    );
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(TrackerError::io("Unable to read confirmation"))?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Saves a product's retained topics to a timestamped state file before they get deleted
+fn backup_product(
+    retained: &BTreeMap<String, String>,
+    discovery_prefix: &str,
+    product_hash: &str,
+) -> Result<(), TrackerError> {
+    let path = format!(
+        "tkpd-backup-{product_hash}-{}.json",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    let backup = state::from_retained(retained.clone(), discovery_prefix);
+    std::fs::write(&path, serde_json::to_string_pretty(&backup)?).map_err(TrackerError::io(
+        format!("Unable to write backup {path}, not deleting anything"),
+    ))?;
+    info!("Backed up retained topics to {path}. Use --restore {path} to undo the delete");
+    Ok(())
+}
+
+/// Rounds half up to the nearest multiple of `step`, so Rp. 99.900 becomes Rp. 100.000
+const fn round_price(price: i64, step: i64) -> i64 {
+    (price + step / 2) / step * step
+}
+
+#[allow(clippy::cast_precision_loss)] // Prices are nowhere near 2^52
+fn percent_change(previous: i64, current: i64) -> f64 {
+    (current - previous) as f64 / previous as f64 * 100.0
+}
+
+/// Rates how attractive the current price is from 0 to 100
+///
+/// Up to 40 points for the listed discount (maxed at 50% off), up to 40 for being close to the
+/// lowest price seen (none once 20% above it) and 20 for an active campaign.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)] // Clamped to 0..=100 before the cast
+fn deal_score(price: i64, lowest_price: i64, discount_percent: f64, campaign_active: bool) -> u8 {
+    let discount = (discount_percent / 50.0).clamp(0.0, 1.0) * 40.0;
+    let above_lowest = if lowest_price > 0 {
+        (price - lowest_price) as f64 / lowest_price as f64
+    } else {
+        0.0
+    };
+    let near_lowest = (1.0 - above_lowest / 0.2).clamp(0.0, 1.0) * 40.0;
+    let campaign = if campaign_active { 20.0 } else { 0.0 };
+    (discount + near_lowest + campaign)
+        .round()
+        .clamp(0.0, 100.0) as u8
+}
+
+/// Describes why a price looks like an API glitch or a mispriced listing, if it does
+fn detect_price_anomaly(
+    previous: Option<i64>,
+    price: i64,
+    threshold: Option<f64>,
+    floor: Option<i64>,
+) -> Option<String> {
+    if let Some(floor) = floor
+        && price < floor
+    {
+        return Some(format!("Rp. {price} is below the Rp. {floor} floor"));
+    }
+
+    let (Some(previous), Some(threshold)) = (previous, threshold) else {
+        return None;
+    };
+    if previous == 0 {
+        return None;
+    }
+    let change = percent_change(previous, price);
+    (change.abs() > threshold).then(|| {
+        format!("Rp. {previous} -> Rp. {price} is a {change:+.1}% change, over the {threshold}% threshold")
+    })
+}
+
+/// Warns when the retained ownership marker shows another instance managing this product
+fn check_ownership(previous: &HashMap<String, String>, instance_id: &str, config_hash: &str) {
+    let Some(meta) = previous
+        .get("meta")
+        .and_then(|m| serde_json::from_str::<Value>(m).ok())
     else {
-        panic!(
-            "Unable to fetch product content detail - It seems like Tokopedia changed their API!"
-        )
+        return;
     };
 
-    println!("{data}");
-    let product_name = data["name"]
-        .as_str()
-        .expect("Unable to decode product name");
-    let product_price = data["price"]["value"]
-        .as_i64()
-        .expect("Unable to decode product price");
-    let product_stock = data["stock"]["value"]
-        .as_str()
-        .and_then(|f| f.parse::<i64>().ok())
-        .expect("Unable to decode product stock");
-
-    info!("Product name: {}", product_name);
-    info!("Price: Rp. {product_price}");
-    info!("Stock: {product_stock}");
-
-    let device_info = json!({
-        "manufacturer": shop_domain,
-        "model_id": product_name,
-        "model": "ha-tkpd",
-        "identifiers": format!("tkpdprice-{product_hash}"),
-        "serial_number": format!("{product_hash}"),
-        "sw_version": env!("CARGO_PKG_VERSION"),
-        "configuration_url": format!("https://tokopedia.com/{shop_domain}/{product_key}"),
-        "name": product_name
-    });
+    let owner = meta["instance_id"].as_str().unwrap_or_default();
+    let owner_config = meta["config_hash"].as_str().unwrap_or_default();
+    if owner != instance_id {
+        warn!(
+            "This product was last published by instance {owner:?} - Two schedulers might be fighting over the same topics!"
+        );
+    } else if owner_config != config_hash {
+        info!("Publish settings changed since the last run ({owner_config} -> {config_hash})");
+    }
+}
 
-    // Product name
-    mqtt_client
-        .publish(
-            format!(
-                "{}/sensor/tkpd-{product_hash}/name/config",
-                args.ha_mqtt_discovery_topic
-            ),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            json!({
-                "device": device_info,
-                "platform": "sensor",
-                "force_update": true,
-                "unique_id": format!("tkpdprice-{product_hash}-name"),
-                "state_topic": format!("tkpdprice/{product_hash}/name"),
-                "name": "Name"
-            })
-            .to_string(),
-        )
-        .expect("Unable to send monetary config");
-
-    // Product price
-    mqtt_client
-        .publish(
-            format!(
-                "{}/sensor/tkpd-{product_hash}/price/config",
-                args.ha_mqtt_discovery_topic
-            ),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            json!({
-                "device": device_info,
-                "platform": "sensor",
-                "device_class": "monetary",
-                "unit_of_measurement": "IDR",
-                "force_update": true,
-                "unique_id": format!("tkpdprice-{product_hash}-price"),
-                "state_topic": format!("tkpdprice/{product_hash}/price"),
-                "name": "Price"
-            })
-            .to_string(),
-        )
-        .expect("Unable to send monetary config");
-
-    // Product stock
-    mqtt_client
-        .publish(
-            format!(
-                "{}/sensor/tkpd-{product_hash}/stock/config",
-                args.ha_mqtt_discovery_topic,
-            ),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            json!({
-                "device": device_info,
-                "platform": "sensor",
-                "force_update": true,
-                "unique_id": format!("tkpdprice-{product_hash}-stock"),
-                "state_topic": format!("tkpdprice/{product_hash}/stock"),
-                "unit_of_measurement": "pcs",
-                "suggested_display_precision": 0,
-                "icon": "mdi:numeric",
-                "name": "Stock"
-            })
-            .to_string(),
-        )
-        .expect("Unable to send stock config");
-    mqtt_client
-        .publish(
-            format!(
-                "{}/sensor/tkpd-{product_hash}/updated-at/config",
-                args.ha_mqtt_discovery_topic
-            ),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            json!({
-                "device": device_info,
-                "platform": "sensor",
-                "entity_category": "diagnostic",
-                "device_class": "timestamp",
-                "force_update": false,
-                "enabled_by_default": true,
-                "unique_id": format!("tkpdprice-{product_hash}-updatedat"),
-                "state_topic": format!("tkpdprice/{product_hash}/updated-at"),
-                "name": "Last update"
-            })
-            .to_string(),
-        )
-        .expect("Unable to send updated at config");
-    mqtt_client
-        .publish(
-            format!(
-                "{}/sensor/tkpd-{product_hash}/scraper-version/config",
-                args.ha_mqtt_discovery_topic
-            ),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            json!({
-                "device": device_info,
-                "platform": "sensor",
-                "entity_category": "diagnostic",
-                "force_update": false,
-                "icon": "mdi:cogs",
-                "unique_id": format!("tkpdprice-{product_hash}-scraperversion"),
-                "state_topic": format!("tkpdprice/{product_hash}/scraper-version"),
-                "name": "Scraper version"
-            })
-            .to_string(),
-        )
-        .expect("Unable to send scraper version config");
+/// Prints a message a dry run would have published
+fn print_dry_run(publish: &Publish) {
+    let retained = if publish.retain { " (retained)" } else { "" };
+    println!(
+        "{}{retained}: {}",
+        publish.topic,
+        String::from_utf8_lossy(&publish.payload)
+    );
+}
 
-    // Send data
-    mqtt_client
-        .publish(
-            format!("tkpdprice/{product_hash}/name"),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            product_name,
-        )
-        .expect("Unable to update name value");
-    mqtt_client
-        .publish(
-            format!("tkpdprice/{product_hash}/price"),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            product_price.to_string(),
-        )
-        .expect("Unable to update price value");
-    mqtt_client
-        .publish(
-            format!("tkpdprice/{product_hash}/stock"),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            product_stock.to_string(),
-        )
-        .expect("Unable to update price value");
-    mqtt_client
-        .publish(
-            format!("tkpdprice/{product_hash}/updated-at"),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            Utc::now().to_rfc3339(),
-        )
-        .expect("Unable to update last updated at data");
-    mqtt_client
-        .publish(
-            format!("tkpdprice/{product_hash}/scraper-version"),
-            rumqttc::QoS::AtLeastOnce,
-            true,
-            env!("CARGO_PKG_VERSION"),
-        )
-        .expect("Unable to update scraper version data");
+/// Prints a message `--print-discovery` would have retained, ready for `mosquitto_pub -r`
+fn print_retained(publish: &Publish) {
+    if publish.retain {
+        println!(
+            "{} {}",
+            publish.topic,
+            String::from_utf8_lossy(&publish.payload)
+        );
+    }
+}
 
-    mqtt_client
-        .disconnect()
-        .expect("Unable to disconnect from MQTT");
+/// Prints a watched message, comparing it with the last value seen on the same topic
+fn print_watched(values: &mut HashMap<String, String>, publish: &Publish) {
+    let Some((product_hash, key)) = publish
+        .topic
+        .strip_prefix("tkpdprice/")
+        .and_then(|t| t.split_once('/'))
+    else {
+        return;
+    };
+    let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+    let product = values
+        .get(&format!("tkpdprice/{product_hash}/name"))
+        .map_or(product_hash, String::as_str);
+    let time = Utc::now().format("%H:%M:%S");
 
-    mqtt_thread
-        .join()
-        .expect("MQTT Event loop exited abnormally. Messages might not be fully published!");
+    match values.get(&publish.topic) {
+        _ if payload.is_empty() => println!("{time} {product} {key}: (deleted)"),
+        Some(old) if *old == payload => println!("{time} {product} {key}: {payload} (unchanged)"),
+        Some(old) => println!("{time} {product} {key}: {old} -> {payload}"),
+        None => println!("{time} {product} {key}: {payload}"),
+    }
+    values.insert(publish.topic.clone(), payload);
+}
 
-    info!("Everything looks successful. Exiting...");
+/// Prints an observation shared by another tracker, and adds it to the price history when kept
+fn collect_shared(
+    args: &Args,
+    values: &HashMap<String, String>,
+    observation: &share::Observation,
+) -> Result<(), TrackerError> {
+    let product_hash = observation.product.as_str();
+    // This instance's own observations come back too, at the time its last run published
+    let own = values
+        .get(&format!("tkpdprice/{product_hash}/updated-at"))
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+        .is_some_and(|at| at == observation.time);
+    if own {
+        return Ok(());
+    }
+
+    let product = values
+        .get(&format!("tkpdprice/{product_hash}/name"))
+        .map_or(product_hash, String::as_str);
+    println!(
+        "{} {product} shared price: {} (seen at {})",
+        Utc::now().format("%H:%M:%S"),
+        observation.price,
+        observation.time.format("%H:%M:%S")
+    );
+    if let Some(path) = &args.history_csv {
+        let signer = args
+            .history_key
+            .as_deref()
+            .map(history::Signer::load)
+            .transpose()?;
+        history::append_csv(
+            path,
+            signer.as_ref(),
+            observation.time,
+            product_hash,
+            "shared",
+            observation.price,
+            None,
+        )?;
+    }
+    Ok(())
 }
 
-// https://stackoverflow.com/questions/27650312/show-u8-slice-in-hex-representation
-struct HexSlice<'a>(&'a [u8]);
+/// Prints a human readable comparison between retained values and freshly scraped data
+fn print_diff(previous: &HashMap<String, String>, args: &Args, product: &ProductSnapshot) {
+    if previous.is_empty() {
+        println!("No previously published data found for this product");
+        return;
+    }
+    if let Some(updated_at) = previous.get("updated-at") {
+        println!("Last published at {updated_at}");
+    }
 
-impl fmt::LowerHex for HexSlice<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if f.alternate() {
-            write!(f, "0x")?;
+    // Compared as `track` would publish them, so a rounded price or renamed product isn't a change
+    let name = display_name(args, &product.name);
+    let campaign = product
+        .campaign
+        .as_ref()
+        .map_or("None", |campaign| campaign.name.as_str());
+    for (label, key, current) in [
+        ("Name", "name", name.as_str()),
+        ("Campaign", "campaign", campaign),
+    ] {
+        match previous.get(key) {
+            Some(old) if old == current => println!("{label}: {current} (unchanged)"),
+            Some(old) => println!("{label}: {old} -> {current}"),
+            None => println!("{label}: (none) -> {current}"),
         }
-        for &byte in self.0 {
-            write!(f, "{byte:0>2x}")?;
+    }
+
+    let price = args
+        .price_rounding
+        .map_or(product.price, |step| round_price(product.price, step));
+    for (label, key, new) in [("Price", "price", price), ("Stock", "stock", product.stock)] {
+        match previous.get(key).and_then(|v| v.parse::<i64>().ok()) {
+            Some(old) if old == new => println!("{label}: {new} (unchanged)"),
+            Some(old) => println!("{label}: {old} -> {new} ({:+})", new - old),
+            None => println!("{label}: (none) -> {new}"),
         }
-        Ok(())
     }
 }
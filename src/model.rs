@@ -0,0 +1,167 @@
+//! Typed shapes of the PDP GraphQL response, covering only the fields this tool reads.
+//!
+//! Tokopedia isn't consistent about types, numbers regularly arrive as strings and optional
+//! objects as `null`. Everything not needed to track a product is optional here, so API drift
+//! only fails decoding when a tracked field disappears, and serde names that field.
+//!
+//! Layout components are borrowed from the response body as raw JSON, so only the components this
+//! tool reads get parsed into values.
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use serde_json::value::RawValue;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PdpGetLayoutResponse<'a> {
+    #[serde(borrow, default)]
+    pub data: Option<PdpData<'a>>,
+    #[serde(default)]
+    pub errors: Option<Vec<GraphQlError>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GraphQlError {
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdpData<'a> {
+    #[serde(borrow, default)]
+    pub pdp_get_layout: Option<PdpGetLayout<'a>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PdpGetLayout<'a> {
+    /// Kept raw, decoded into [`BasicInfo`] along with the product
+    #[serde(default)]
+    pub basic_info: Value,
+    #[serde(borrow, default)]
+    pub components: Vec<Component<'a>>,
+}
+
+/// A block of the product page, its data left unparsed until the block is needed
+#[derive(Debug, Default, Deserialize)]
+pub struct Component<'a> {
+    #[serde(default)]
+    pub name: String,
+    /// Array of data objects, `None` when null
+    #[serde(borrow, default)]
+    pub data: Option<&'a RawValue>,
+}
+
+/// The `product_content` component
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductContent {
+    pub name: String,
+    pub price: Price,
+    pub stock: Stock,
+    #[serde(default)]
+    pub campaign: Option<Campaign>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Price {
+    #[serde(deserialize_with = "lenient_i64")]
+    pub value: i64,
+    /// Discount shown on the listing, e.g. "12%"
+    #[serde(default, deserialize_with = "lenient_f64")]
+    pub disc_percentage: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Stock {
+    #[serde(deserialize_with = "lenient_i64")]
+    pub value: i64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Campaign {
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub is_active: bool,
+    #[serde(default)]
+    pub campaign_type_name: Option<String>,
+    #[serde(default, deserialize_with = "lenient_f64")]
+    pub percentage_amount: Option<f64>,
+    #[serde(default, deserialize_with = "lenient_opt_i64")]
+    pub discounted_price: Option<i64>,
+    /// Jakarta time, e.g. "2025-03-01 00:00:00"
+    #[serde(default)]
+    pub start_date: Option<String>,
+    #[serde(default, deserialize_with = "lenient_opt_i64")]
+    pub end_date_unix: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_opt_i64")]
+    pub stock: Option<i64>,
+    #[serde(default, deserialize_with = "lenient_opt_i64")]
+    pub original_stock: Option<i64>,
+}
+
+/// Layout level listing info
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BasicInfo {
+    #[serde(default, deserialize_with = "lenient_f64")]
+    pub weight: Option<f64>,
+    #[serde(default)]
+    pub weight_unit: Option<String>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    #[serde(default)]
+    pub category: Option<Category>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Category {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Category levels from the root down
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub detail: Vec<CategoryLevel>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CategoryLevel {
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Reads a number that may also come as a string, like `"12"` or `"12%"`
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().trim_end_matches('%').trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn lenient_f64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Ok(number(&Value::deserialize(deserializer)?))
+}
+
+fn lenient_opt_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    Ok(value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok())))
+}
+
+fn lenient_i64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+        .ok_or_else(|| serde::de::Error::custom(format!("expected an integer, got {value}")))
+}
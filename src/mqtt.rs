@@ -0,0 +1,343 @@
+//! MQTT connection handling: the event loop task, reading retained topics & publishing devices.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use rumqttc::{AsyncClient, ConnectionError, Event, MqttOptions, Packet, Publish, QoS, Request};
+use serde_json::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::TrackerError;
+
+/// Publish requests buffered between the callers and the MQTT event loop
+const MQTT_REQUEST_QUEUE_SIZE: usize = 10;
+/// Incoming messages buffered per retained read, readers falling further behind skip messages
+const INCOMING_BUFFER_SIZE: usize = 1024;
+/// How long to wait for the broker to replay retained messages after subscribing
+const RETAINED_READ_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to wait for queued messages to be sent when disconnecting
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for the broker to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the broker connection is at, as last seen by the event loop
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Failed(String),
+}
+
+/// A broker connection driven by its own event loop task
+///
+/// Every method takes `&self`, so one publisher can be shared by products tracked concurrently.
+pub struct Publisher {
+    client: AsyncClient,
+    incoming: broadcast::Sender<Publish>,
+    state: watch::Receiver<ConnectionState>,
+    event_loop: JoinHandle<()>,
+    /// Set for [`Publisher::dry_run`], which has no broker to read retained topics from
+    dry_run: bool,
+}
+
+impl Publisher {
+    /// Connects to the broker and spawns the event loop task, which reconnects when the connection
+    /// drops
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime.
+    #[must_use]
+    pub fn connect(options: MqttOptions) -> Self {
+        Self::spawn(options, false)
+    }
+
+    /// Like [`Publisher::connect`], but the event loop stops at the first connection error so
+    /// anything not yet sent fails instead of waiting for a reconnect
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime.
+    #[must_use]
+    pub fn connect_fail_fast(options: MqttOptions) -> Self {
+        Self::spawn(options, true)
+    }
+
+    /// A publisher that never connects, handing every publish to `on_publish` instead. Nothing is
+    /// retained, so reads come back empty right away
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime.
+    #[must_use]
+    pub fn dry_run(on_publish: impl Fn(&Publish) + Send + 'static) -> Self {
+        let (request_tx, request_rx) = flume::bounded(MQTT_REQUEST_QUEUE_SIZE);
+        let client = AsyncClient::from_senders(request_tx);
+        let (incoming, _) = broadcast::channel::<Publish>(INCOMING_BUFFER_SIZE);
+        let (_, state) = watch::channel(ConnectionState::Connected);
+
+        let event_loop = tokio::spawn(async move {
+            while let Ok(request) = request_rx.recv_async().await {
+                match request {
+                    Request::Publish(publish) => on_publish(&publish),
+                    Request::Disconnect(_) => break,
+                    request => debug!(target: "mqtt", "Dry run, skipping = {request:?}"),
+                }
+            }
+        });
+
+        Self {
+            client,
+            incoming,
+            state,
+            event_loop,
+            dry_run: true,
+        }
+    }
+
+    fn spawn(options: MqttOptions, fail_fast: bool) -> Self {
+        // Publishes are queued to the event loop and pipelined up to the inflight limit. The small
+        // queue makes `publish` wait instead of buffering every message in memory
+        let (client, mut event_loop) = AsyncClient::new(options, MQTT_REQUEST_QUEUE_SIZE);
+        let (incoming, _) = broadcast::channel::<Publish>(INCOMING_BUFFER_SIZE);
+        let incoming_tx = incoming.clone();
+        let (state_tx, state) = watch::channel(ConnectionState::Connecting);
+
+        let event_loop = tokio::spawn(async move {
+            info!(target: "mqtt", "MQTT client running");
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        debug!(target: "mqtt", "Received = {publish:?}");
+                        // Nobody listens unless retained values are being read
+                        let _ = incoming_tx.send(publish);
+                    }
+                    Ok(Event::Incoming(Packet::ConnAck(connack))) => {
+                        debug!(target: "mqtt", "Connected = {connack:?}");
+                        state_tx.send_replace(ConnectionState::Connected);
+                    }
+                    Ok(notification) => {
+                        debug!(target: "mqtt", "Message = {notification:?}");
+                    }
+                    Err(ConnectionError::RequestsDone) => break,
+                    Err(ConnectionError::MqttState(rumqttc::StateError::Io(e)))
+                        if e.kind() == std::io::ErrorKind::ConnectionAborted =>
+                    {
+                        info!(target: "mqtt", "All MQTT message has been pushed. Stopping gracefully...");
+                        break;
+                    }
+                    Err(e) => {
+                        error!(target: "mqtt", "Unknown error - {e:?}");
+                        state_tx.send_replace(ConnectionState::Failed(e.to_string()));
+                        if fail_fast {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self {
+            client,
+            incoming,
+            state,
+            event_loop,
+            dry_run: false,
+        }
+    }
+
+    /// Waits until the broker accepts the connection
+    ///
+    /// # Errors
+    ///
+    /// Fails when the first connection attempt fails or the broker doesn't answer in time.
+    pub async fn wait_connected(&self) -> Result<(), TrackerError> {
+        let mut watcher = self.state.clone();
+        // Cloned right away, the borrowed state blocks the event loop from updating it
+        let settled = tokio::time::timeout(CONNECT_TIMEOUT, async {
+            watcher
+                .wait_for(|state| *state != ConnectionState::Connecting)
+                .await
+                .map(|state| state.clone())
+        })
+        .await;
+        let state = match settled {
+            Ok(Ok(state)) => state,
+            Ok(Err(_)) => ConnectionState::Failed("MQTT event loop stopped".to_string()),
+            Err(_) => ConnectionState::Failed("Timed out waiting for the broker".to_string()),
+        };
+        match state {
+            ConnectionState::Failed(reason) => Err(TrackerError::MqttUnreachable(reason)),
+            ConnectionState::Connecting | ConnectionState::Connected => Ok(()),
+        }
+    }
+
+    /// The underlying client, for publishing anything not covered here
+    #[must_use]
+    pub const fn client(&self) -> &AsyncClient {
+        &self.client
+    }
+
+    /// Collects every retained message matching the topic filters, keyed by topic
+    ///
+    /// # Errors
+    ///
+    /// Fails when the event loop is gone and the subscription can't be requested.
+    pub async fn read_retained(
+        &self,
+        filters: &[String],
+    ) -> Result<BTreeMap<String, String>, TrackerError> {
+        // Waiting for a replay that can't come would only hold the run up
+        if self.dry_run {
+            return Ok(BTreeMap::new());
+        }
+        // Listen before subscribing so the replay can't be missed
+        let mut incoming = self.incoming.subscribe();
+        for filter in filters {
+            self.client.subscribe(filter, QoS::AtLeastOnce).await?;
+        }
+
+        // Retained messages are replayed right after subscribing; stop once the broker goes quiet.
+        // Other readers' replays arrive here too, so only keep what these filters match
+        let mut retained = BTreeMap::new();
+        while let Ok(received) = tokio::time::timeout(RETAINED_READ_TIMEOUT, incoming.recv()).await
+        {
+            let publish = match received {
+                Ok(publish) => publish,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(target: "mqtt", "Skipped {skipped} incoming messages while reading retained topics");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if !publish.retain
+                || publish.payload.is_empty()
+                || !filters
+                    .iter()
+                    .any(|filter| rumqttc::matches(&publish.topic, filter))
+            {
+                continue;
+            }
+            retained.insert(
+                publish.topic,
+                String::from_utf8_lossy(&publish.payload).into_owned(),
+            );
+        }
+
+        for filter in filters {
+            self.client.unsubscribe(filter).await?;
+        }
+        Ok(retained)
+    }
+
+    /// Subscribes to the topic filters and passes on every message received from then on
+    ///
+    /// Retained messages matching the filters are replayed first. Messages received for other
+    /// subscriptions of this publisher come through too.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the event loop is gone.
+    pub async fn subscribe(
+        &self,
+        filters: &[String],
+    ) -> Result<broadcast::Receiver<Publish>, TrackerError> {
+        let incoming = self.incoming.subscribe();
+        for filter in filters {
+            self.client.subscribe(filter, QoS::AtLeastOnce).await?;
+        }
+        Ok(incoming)
+    }
+
+    /// Reads the retained values under `tkpdprice/<hash>/`, keyed by their topic suffix
+    ///
+    /// # Errors
+    ///
+    /// Fails when the event loop is gone.
+    pub async fn read_retained_values(
+        &self,
+        product_hash: &str,
+    ) -> Result<HashMap<String, String>, TrackerError> {
+        let prefix = format!("tkpdprice/{product_hash}/");
+        let values: HashMap<String, String> = self
+            .read_retained(&[format!("{prefix}+")])
+            .await?
+            .into_iter()
+            .filter_map(|(topic, payload)| {
+                Some((topic.strip_prefix(&prefix)?.to_string(), payload))
+            })
+            .collect();
+        debug!(target: "mqtt", "Retained values = {values:?}");
+        Ok(values)
+    }
+
+    /// Publishes retained discovery configs, each under the component named by its `"platform"`
+    ///
+    /// # Errors
+    ///
+    /// Fails when the event loop is gone.
+    pub async fn publish_device(
+        &self,
+        discovery_prefix: &str,
+        product_hash: &str,
+        configs: &[(String, Value)],
+    ) -> Result<(), TrackerError> {
+        for (key, config) in configs {
+            let component = config["platform"].as_str().unwrap_or("sensor");
+            self.client
+                .publish(
+                    format!("{discovery_prefix}/{component}/tkpd-{product_hash}/{key}/config"),
+                    QoS::AtLeastOnce,
+                    true,
+                    config.to_string(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Publishes retained sensor states under `tkpdprice/<hash>/<key>`
+    ///
+    /// # Errors
+    ///
+    /// Fails when the event loop is gone.
+    pub async fn publish_states(
+        &self,
+        product_hash: &str,
+        states: Vec<(String, String)>,
+    ) -> Result<(), TrackerError> {
+        for (key, state) in states {
+            self.client
+                .publish(
+                    format!("tkpdprice/{product_hash}/{key}"),
+                    QoS::AtLeastOnce,
+                    true,
+                    state,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Disconnects once everything queued has been sent and waits for the event loop to finish
+    ///
+    /// # Errors
+    ///
+    /// Fails when the disconnect can't be requested, the event loop task panicked or the broker
+    /// doesn't take the queued messages in time.
+    pub async fn disconnect(self) -> Result<(), TrackerError> {
+        self.client.disconnect().await?;
+        match tokio::time::timeout(DISCONNECT_TIMEOUT, self.event_loop).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(TrackerError::MqttShutdown(format!(
+                "MQTT Event loop exited abnormally - {e}"
+            ))),
+            Err(_) => Err(TrackerError::MqttShutdown(
+                "Timed out sending queued MQTT messages".to_string(),
+            )),
+        }
+    }
+}
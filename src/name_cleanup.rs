@@ -0,0 +1,209 @@
+//! Tidies up seller written product titles before they are used as HA device names & entity IDs.
+//!
+//! Tokopedia titles are SEO bait more often than not: emoji, shouted promo phrases and
+//! everything the seller could think of crammed in 150 characters.
+
+/// Promo phrases sellers stuff into titles, compared against uppercased alphanumeric words
+const PROMO_PHRASES: &[&[&str]] = &[
+    &["BISA", "COD"],
+    &["COD"],
+    &["GRATIS", "ONGKIR"],
+    &["FREE", "ONGKIR"],
+    &["FREE", "SHIPPING"],
+    &["READY", "STOCK"],
+    &["READY", "STOK"],
+    &["BEST", "SELLER"],
+    &["FLASH", "SALE"],
+    &["PROMO"],
+    &["DISKON"],
+    &["MURAH"],
+    &["TERMURAH"],
+    &["HOT", "ITEM"],
+];
+
+const fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{1F000}'..='\u{1FAFF}'
+        | '\u{2600}'..='\u{27BF}'
+        | '\u{2B00}'..='\u{2BFF}'
+        | '\u{2190}'..='\u{21FF}'
+        | '\u{3030}' | '\u{303D}' | '\u{3297}' | '\u{3299}'
+        | '\u{200D}' | '\u{20E3}' | '\u{FE0F}'
+    )
+}
+
+/// Filler words left out of slugs, English & Indonesian
+const SLUG_STOPWORDS: &[&str] = &[
+    "a", "an", "and", "dan", "dengan", "di", "for", "of", "the", "untuk", "with", "yang",
+];
+
+/// Uppercased alphanumeric part of a word, used to match it against promo phrases
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Strips emoji & promo phrases from a product name
+#[must_use]
+pub fn clean(name: &str) -> String {
+    let without_emoji: String = name.chars().filter(|c| !is_emoji(*c)).collect();
+    let words: Vec<&str> = without_emoji.split_whitespace().collect();
+    let normalized: Vec<String> = words.iter().map(|w| normalize(w)).collect();
+
+    let mut kept = Vec::with_capacity(words.len());
+    let mut i = 0;
+    'words: while i < words.len() {
+        for phrase in PROMO_PHRASES {
+            let end = i + phrase.len();
+            if end <= normalized.len() && normalized[i..end] == **phrase {
+                i = end;
+                continue 'words;
+            }
+        }
+
+        // Separators and "!!!" left dangling by removed phrases
+        if normalized[i].is_empty() && !words[i].chars().any(char::is_alphabetic) {
+            i += 1;
+            continue;
+        }
+        kept.push(words[i].trim_end_matches('!'));
+        i += 1;
+    }
+
+    let cleaned = kept.join(" ");
+    if cleaned.is_empty() {
+        // Everything was spam, better a noisy name than no name
+        name.trim().to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Shortens a name to at most `max_chars` characters, cutting at a word boundary when possible
+#[must_use]
+pub fn truncate(name: &str, max_chars: usize) -> String {
+    if name.chars().count() <= max_chars {
+        return name.to_string();
+    }
+
+    // Leave room for the ellipsis
+    let cut: String = name.chars().take(max_chars.saturating_sub(1)).collect();
+    let cut = match cut.rfind(' ') {
+        Some(space) if space > cut.len() / 2 => &cut[..space],
+        _ => &cut,
+    };
+    format!("{}…", cut.trim_end())
+}
+
+/// Short `snake_case` slug of a name for entity IDs
+///
+/// Made of the first `max_words` significant words folded to ASCII, so "Kopi Arabika Gayo untuk
+/// Espresso 1kg" becomes `kopi_arabika_gayo_espresso` with 4 words.
+#[must_use]
+pub fn slug(name: &str, max_words: usize) -> String {
+    let folded: String = clean(name)
+        .chars()
+        .filter_map(fold_ascii)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let words: Vec<&str> = folded
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty() && !SLUG_STOPWORDS.contains(word))
+        .take(max_words)
+        .collect();
+    if words.is_empty() {
+        "product".to_string()
+    } else {
+        words.join("_")
+    }
+}
+
+/// The ASCII letter an accented Latin letter is based on, `None` for other non-ASCII characters
+const fn fold_ascii(c: char) -> Option<char> {
+    Some(match c {
+        c if c.is_ascii() => c,
+        'à'..='å' | 'À'..='Å' => 'a',
+        'ç' | 'Ç' => 'c',
+        'è'..='ë' | 'È'..='Ë' => 'e',
+        'ì'..='ï' | 'Ì'..='Ï' => 'i',
+        'ñ' | 'Ñ' => 'n',
+        'ò'..='ö' | 'ø' | 'Ò'..='Ö' | 'Ø' => 'o',
+        'ù'..='ü' | 'Ù'..='Ü' => 'u',
+        'ý' | 'ÿ' | 'Ý' => 'y',
+        // Separates words like the characters dropped from slugs anyway
+        _ if c.is_whitespace() => ' ',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clean_strips_emoji_and_promo_phrases() {
+        assert_eq!(
+            clean("🔥 READY STOCK Kopi Arabika Gayo 1kg - BISA COD!!!"),
+            "Kopi Arabika Gayo 1kg"
+        );
+        assert_eq!(clean("Gratis-Ongkir Lampu LED"), "Gratis-Ongkir Lampu LED");
+        assert_eq!(clean("GRATIS ONGKIR Lampu LED"), "Lampu LED");
+    }
+
+    #[test]
+    fn clean_only_matches_whole_words() {
+        assert_eq!(clean("Codex Murahan"), "Codex Murahan");
+        assert_eq!(clean("Lampu Meja Keren!!!"), "Lampu Meja Keren");
+    }
+
+    #[test]
+    fn clean_keeps_names_that_are_all_spam() {
+        assert_eq!(clean("  PROMO MURAH!!! 🔥🔥 "), "PROMO MURAH!!! 🔥🔥");
+    }
+
+    #[test]
+    fn truncate_keeps_short_names() {
+        assert_eq!(truncate("Kopi Gayo", 9), "Kopi Gayo");
+        assert_eq!(truncate("Kopi Gayo", 20), "Kopi Gayo");
+    }
+
+    #[test]
+    fn truncate_cuts_at_a_word_boundary() {
+        assert_eq!(
+            truncate("Kopi Arabika Gayo Espresso", 20),
+            "Kopi Arabika Gayo…"
+        );
+        // A space early in the name would leave too little of it
+        assert_eq!(truncate("Kopi Arabika Gayo", 10), "Kopi Arab…");
+    }
+
+    #[test]
+    fn truncate_counts_characters_not_bytes() {
+        assert_eq!(truncate("Café Olé Crème Brûlée", 12), "Café Olé…");
+        assert_eq!(truncate("ÉÉÉÉÉÉÉÉÉÉ", 5), "ÉÉÉÉ…");
+        assert_eq!(truncate("🔥🔥🔥🔥🔥🔥", 3).chars().count(), 3);
+    }
+
+    #[test]
+    fn slug_keeps_significant_words() {
+        assert_eq!(
+            slug("Kopi Arabika Gayo untuk Espresso 1kg", 4),
+            "kopi_arabika_gayo_espresso"
+        );
+        assert_eq!(slug("READY STOCK Lampu-Meja LED", 2), "lampu_meja");
+    }
+
+    #[test]
+    fn slug_folds_accents_and_drops_other_scripts() {
+        assert_eq!(slug("Café Crème", 4), "cafe_creme");
+        assert_eq!(slug("日本茶 Green Tea", 4), "green_tea");
+    }
+
+    #[test]
+    fn slug_falls_back_when_nothing_is_left() {
+        assert_eq!(slug("🔥🔥", 4), "product");
+        assert_eq!(slug("日本茶", 4), "product");
+    }
+}
@@ -0,0 +1,48 @@
+//! Opt-in `--share` of price observations on a common topic, and collecting those of others.
+//!
+//! An observation is only the product hash, its price and when it was seen. The hash comes from
+//! the listing alone, so everyone tracking a product publishes under the same one, and nothing in
+//! an observation tells who made it.
+
+use chrono::{DateTime, Utc};
+use rumqttc::Publish;
+use serde_json::{Value, json};
+
+/// A price seen by a tracker sharing on the topic
+pub struct Observation {
+    pub product: String,
+    pub price: i64,
+    pub time: DateTime<Utc>,
+}
+
+/// Topic the observations of a product are published on
+pub fn topic(prefix: &str, product_hash: &str) -> String {
+    format!("{prefix}/{product_hash}")
+}
+
+/// JSON of an observation, with its time in RFC 3339
+pub fn payload(product_hash: &str, price: i64, time: DateTime<Utc>) -> String {
+    json!({
+        "product": product_hash,
+        "price": price,
+        "time": time.to_rfc3339(),
+    })
+    .to_string()
+}
+
+/// Reads an observation published under `prefix`, ignoring anything else
+pub fn parse(prefix: &str, publish: &Publish) -> Option<Observation> {
+    let product_hash = publish.topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    let payload: Value = serde_json::from_slice(&publish.payload).ok()?;
+    // The topic is what others subscribe by, a payload claiming another product is malformed
+    if payload["product"] != product_hash {
+        return None;
+    }
+    Some(Observation {
+        product: product_hash.to_string(),
+        price: payload["price"].as_i64().filter(|price| *price > 0)?,
+        time: DateTime::parse_from_rfc3339(payload["time"].as_str()?)
+            .ok()?
+            .with_timezone(&Utc),
+    })
+}
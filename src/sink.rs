@@ -0,0 +1,102 @@
+//! Where a scrape goes, for when the price is wanted without Home Assistant.
+//!
+//! The `mqtt` sink is the tracker itself: it reads the previous values back from the broker and
+//! publishes discovery, states & triggers. The other sinks need no broker and write each scrape as
+//! one line of JSON.
+
+use std::io::Write;
+use std::path::Path;
+
+use chrono::Utc;
+use clap::ValueEnum;
+use serde_json::{Map, Value, json};
+use tkpd_tracker::{ProductSnapshot, Target, TrackerError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Sink {
+    // Publish to Home Assistant through the broker
+    Mqtt,
+    // Print a JSON line to stdout
+    StdoutJson,
+    // Append a JSON line to the --output file
+    File,
+}
+
+impl Sink {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Mqtt => "mqtt",
+            Self::StdoutJson => "stdout-json",
+            Self::File => "file",
+        }
+    }
+
+    /// Writes a scrape built by [`record`] as one line of JSON
+    pub fn write(self, output: Option<&Path>, record: &Value) -> Result<(), TrackerError> {
+        let Some(path) = output.filter(|_| self == Self::File) else {
+            println!("{record}");
+            return Ok(());
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(TrackerError::io(format!(
+                "Unable to open {}",
+                path.display()
+            )))?;
+        writeln!(file, "{record}").map_err(TrackerError::io(format!(
+            "Unable to write {}",
+            path.display()
+        )))
+    }
+}
+
+/// A scrape with the name & price the `mqtt` sink would publish, and the extra sensor values by key
+pub fn record(
+    target: &Target,
+    product_hash: &str,
+    name: &str,
+    price: i64,
+    product: &ProductSnapshot,
+    extras: Vec<(String, String)>,
+) -> Value {
+    let upcoming_campaign = product.upcoming_campaign.as_ref().map(|campaign| {
+        json!({
+            "name": campaign.name,
+            "starts_at": campaign.starts_at.map(|start| start.to_rfc3339()),
+            "price": campaign.price,
+        })
+    });
+    let campaign = product.campaign.as_ref().map(|campaign| {
+        json!({
+            "name": campaign.name,
+            "ends_at": campaign.ends_at.map(|end| end.to_rfc3339()),
+            "discount_percent": campaign.discount_percent,
+            "stock_remaining_percent": campaign.stock_remaining_percent(),
+        })
+    });
+    let extras: Map<String, Value> = extras
+        .into_iter()
+        .map(|(key, value)| (key, Value::String(value)))
+        .collect();
+
+    json!({
+        "product": product_hash,
+        "shop": target.shop,
+        "key": target.key,
+        "name": name,
+        "original_name": product.name,
+        "price": price,
+        "stock": product.stock,
+        "discount_percent": product.discount_percent(),
+        "campaign": campaign,
+        "upcoming_campaign": upcoming_campaign,
+        "category": product.category_path,
+        "brand": product.brand,
+        "condition": product.condition,
+        "weight": product.weight,
+        "extra": extras,
+        "updated_at": Utc::now().to_rfc3339(),
+    })
+}
@@ -0,0 +1,106 @@
+//! Marketplace-neutral product data and the trait marketplaces implement to provide it.
+//!
+//! Everything after fetching (discovery, states, triggers & the summary) only sees a
+//! [`ProductSnapshot`], so another marketplace only needs a [`PriceSource`] implementation.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::TrackerError;
+
+/// A listing to track, identified the way its marketplace does
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// Store the listing belongs to, e.g. the Tokopedia shop domain
+    pub shop: String,
+    /// Listing id within the store, e.g. the Tokopedia product key
+    pub key: String,
+    /// Where the listing was opened from, which can change its price, e.g. Tokopedia's `extParam`.
+    /// Not part of what identifies the listing
+    pub context: Option<String>,
+}
+
+/// Somewhere product listings can be fetched from
+pub trait PriceSource {
+    /// Reads the target out of a listing URL of this marketplace
+    ///
+    /// # Errors
+    ///
+    /// Fails when the URL doesn't point at a listing of this marketplace.
+    fn parse_url(&self, url: &str) -> Result<Target, TrackerError>;
+
+    /// Fetches the current state of a listing
+    ///
+    /// # Errors
+    ///
+    /// Fails when the marketplace can't be reached or its response can't be decoded.
+    fn fetch(
+        &self,
+        target: &Target,
+    ) -> impl Future<Output = Result<ProductSnapshot, TrackerError>> + Send;
+}
+
+/// A flash sale or other campaign on a listing
+#[derive(Clone, Debug)]
+pub struct Campaign {
+    pub name: String,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+    /// Price of the listing during the campaign
+    pub price: Option<i64>,
+    pub discount_percent: Option<f64>,
+    /// Units left in the campaign allocation
+    pub stock: Option<i64>,
+    /// Units the campaign started with
+    pub original_stock: Option<i64>,
+}
+
+impl Campaign {
+    /// Share of the campaign allocation still available, from 0 to 100
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // Stock counts are nowhere near 2^52
+    pub fn stock_remaining_percent(&self) -> Option<f64> {
+        let (stock, original) = (self.stock?, self.original_stock?);
+        (original > 0).then(|| (stock as f64 / original as f64 * 100.0).clamp(0.0, 100.0))
+    }
+}
+
+/// A listing as fetched from its marketplace
+#[derive(Clone, Debug)]
+pub struct ProductSnapshot {
+    pub name: String,
+    pub price: i64,
+    pub stock: i64,
+    pub campaign: Option<Campaign>,
+    /// A campaign announced on the listing that hasn't started yet
+    pub upcoming_campaign: Option<Campaign>,
+    /// Discount shown on the listing itself, outside of campaigns
+    pub listing_discount_percent: Option<f64>,
+    pub category: Option<String>,
+    /// Full category path, e.g. "Audio > Headphone"
+    pub category_path: Option<String>,
+    pub brand: Option<String>,
+    /// Shipping weight, always in grams
+    pub weight: Option<f64>,
+    pub dimensions: Option<String>,
+    /// Lowercased listing condition, e.g. "new"
+    pub condition: Option<String>,
+    pub warranty: Option<String>,
+    /// Fields the source reads that its response didn't have, a sign its API changed
+    pub missing_fields: Vec<String>,
+    /// The marketplace data this snapshot was read from, for extra sensors & `--dump-raw`
+    pub raw: Value,
+}
+
+impl ProductSnapshot {
+    /// Discount currently applied, from the campaign when one is running
+    #[must_use]
+    pub fn discount_percent(&self) -> f64 {
+        self.campaign
+            .as_ref()
+            .map_or(self.listing_discount_percent, |campaign| {
+                campaign.discount_percent
+            })
+            .unwrap_or(0.0)
+    }
+}
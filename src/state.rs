@@ -0,0 +1,118 @@
+//! Conversion between retained broker topics and a portable tracker state file.
+//!
+//! The broker is the only place tracker state lives, so exporting it means collecting the
+//! retained discovery configs and values of every `tkpd-` device. Discovery topics are stored
+//! without their prefix, which lets an import move devices to a different discovery prefix.
+
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+
+const STATE_VERSION: u64 = 1;
+
+/// Topic filters covering everything this tool retains on the broker
+#[must_use]
+pub fn topic_filters(discovery_prefix: &str) -> Vec<String> {
+    vec![
+        "tkpdprice/+/+".to_string(),
+        format!("{discovery_prefix}/+/+/+/config"),
+    ]
+}
+
+/// Topic filters covering the retained topics of a single product
+#[must_use]
+pub fn product_topic_filters(discovery_prefix: &str, product_hash: &str) -> Vec<String> {
+    vec![
+        format!("tkpdprice/{product_hash}/+"),
+        format!("{discovery_prefix}/+/tkpd-{product_hash}/+/config"),
+    ]
+}
+
+/// Groups retained topics per product hash into a state document
+#[must_use]
+pub fn from_retained(retained: BTreeMap<String, String>, discovery_prefix: &str) -> Value {
+    let mut products = Map::new();
+    let discovery_prefix = format!("{discovery_prefix}/");
+
+    for (topic, payload) in retained {
+        if let Some(rest) = topic.strip_prefix("tkpdprice/") {
+            let Some((hash, key)) = rest.split_once('/') else {
+                continue;
+            };
+            product_entry(&mut products, hash)["values"][key] = json!(payload);
+        } else if let Some(rest) = topic.strip_prefix(&discovery_prefix) {
+            // <component>/tkpd-<hash>/<key>/config
+            let parts: Vec<&str> = rest.split('/').collect();
+            let [component, node, key, "config"] = parts[..] else {
+                continue;
+            };
+            let Some(hash) = node.strip_prefix("tkpd-") else {
+                continue;
+            };
+            let config: Value = serde_json::from_str(&payload).unwrap_or(Value::String(payload));
+            let product = product_entry(&mut products, hash);
+            if let Some(url) = config["device"]["configuration_url"].as_str() {
+                product["configuration_url"] = json!(url);
+            }
+            product["discovery"][format!("{component}/{key}")] = config;
+        }
+    }
+
+    json!({
+        "version": STATE_VERSION,
+        "exported_at": Utc::now().to_rfc3339(),
+        "products": products,
+    })
+}
+
+fn product_entry<'a>(products: &'a mut Map<String, Value>, hash: &str) -> &'a mut Value {
+    products
+        .entry(hash)
+        .or_insert_with(|| json!({ "discovery": {}, "values": {} }))
+}
+
+/// Turns a state document back into retained topics & payloads
+///
+/// # Errors
+///
+/// Fails on missing or unsupported state versions and malformed discovery entries.
+pub fn to_topics(state: &Value, discovery_prefix: &str) -> Result<Vec<(String, String)>, String> {
+    match state["version"].as_u64() {
+        Some(STATE_VERSION) => {}
+        Some(version) => return Err(format!("Unsupported state version {version}")),
+        None => return Err("Missing state version".to_string()),
+    }
+    let products = state["products"]
+        .as_object()
+        .ok_or("Missing \"products\" object")?;
+
+    let mut topics = Vec::new();
+    for (hash, product) in products {
+        if let Some(discovery) = product["discovery"].as_object() {
+            for (path, config) in discovery {
+                let Some((component, key)) = path.split_once('/') else {
+                    return Err(format!("Invalid discovery entry {path:?} for {hash}"));
+                };
+                let payload = match config {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                topics.push((
+                    format!("{discovery_prefix}/{component}/tkpd-{hash}/{key}/config"),
+                    payload,
+                ));
+            }
+        }
+        if let Some(values) = product["values"].as_object() {
+            for (key, value) in values {
+                let payload = match value {
+                    Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                topics.push((format!("tkpdprice/{hash}/{key}"), payload));
+            }
+        }
+    }
+    Ok(topics)
+}
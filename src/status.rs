@@ -0,0 +1,135 @@
+//! Where a tracking run is at, so a failure can be traced to Tokopedia, decoding or the broker.
+//!
+//! A run moves through resolve → fetch → parse → publish and ends up done, paused or failed. Each
+//! step is logged, and the outcome is published retained on `tkpdprice/<hash>/status` for the
+//! "Run status" diagnostic sensor. Fields Tokopedia stopped sending are published on
+//! `tkpdprice/<hash>/schema-drift` for the "Schema drift" problem sensor.
+
+use chrono::Utc;
+use log::{debug, error, warn};
+use rumqttc::QoS;
+use serde_json::json;
+use tkpd_tracker::{Publisher, TrackerError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the URL & what the previous run published
+    Resolve,
+    /// Requesting the listing from Tokopedia
+    Fetch,
+    /// Decoding the listing & working out what to publish
+    Parse,
+    /// Sending discovery configs, states & triggers
+    Publish,
+    /// Tracking is paused from HA, nothing was scraped
+    Paused,
+    /// Updated too recently for `--min-interval` or requests are paused by the breaker, nothing was
+    /// scraped
+    Skipped,
+    Done,
+}
+
+impl Stage {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Resolve => "resolve",
+            Self::Fetch => "fetch",
+            Self::Parse => "parse",
+            Self::Publish => "publish",
+            Self::Paused => "paused",
+            Self::Skipped => "skipped",
+            Self::Done => "done",
+        }
+    }
+}
+
+pub struct RunStatus {
+    stage: Stage,
+    /// Fields missing from the response, `None` until one was checked
+    missing_fields: Option<Vec<String>>,
+}
+
+impl RunStatus {
+    pub fn new() -> Self {
+        debug!("Run stage: {}", Stage::Resolve.as_str());
+        Self {
+            stage: Stage::Resolve,
+            missing_fields: None,
+        }
+    }
+
+    /// Records the fields a decoded response was missing
+    pub fn checked_schema(&mut self, missing_fields: &[String]) {
+        self.missing_fields = Some(missing_fields.to_vec());
+    }
+
+    pub fn enter(&mut self, stage: Stage) {
+        debug!("Run stage: {}", stage.as_str());
+        self.stage = stage;
+    }
+
+    /// The stage an error happened in. Decoding happens as part of fetching, but is a parse failure
+    const fn failed_stage(&self, error: &TrackerError) -> Stage {
+        match (self.stage, error) {
+            (
+                Stage::Fetch,
+                TrackerError::Decode(_) | TrackerError::Json(_) | TrackerError::SchemaDrift(_),
+            ) => Stage::Parse,
+            (stage, _) => stage,
+        }
+    }
+
+    /// Logs how the run ended and publishes it, unless the broker is what failed
+    pub async fn report(
+        &self,
+        publisher: &Publisher,
+        product_hash: &str,
+        error: Option<&TrackerError>,
+    ) {
+        let payload = match error {
+            None => json!({
+                "state": self.stage.as_str(),
+                "at": Utc::now().to_rfc3339(),
+            }),
+            Some(e) => {
+                let stage = self.failed_stage(e);
+                error!("Run failed at the {} stage", stage.as_str());
+                if e.is_mqtt() {
+                    return;
+                }
+                json!({
+                    "state": "failed",
+                    "stage": stage.as_str(),
+                    "error": e.to_string(),
+                    "exit_code": e.exit_code(),
+                    "at": Utc::now().to_rfc3339(),
+                })
+            }
+        };
+
+        let missing_fields = match error {
+            Some(TrackerError::SchemaDrift(fields)) => Some(fields),
+            _ => self.missing_fields.as_ref(),
+        };
+        let mut messages = vec![("status", payload)];
+        if let Some(fields) = missing_fields {
+            let state = if fields.is_empty() { "OFF" } else { "ON" };
+            messages.push(("schema-drift", json!({ "state": state, "fields": fields })));
+        }
+
+        for (key, payload) in messages {
+            let sent = publisher
+                .client()
+                .publish(
+                    format!("tkpdprice/{product_hash}/{key}"),
+                    QoS::AtLeastOnce,
+                    true,
+                    payload.to_string(),
+                )
+                .await;
+            if let Err(e) = sent {
+                warn!("Unable to publish the run {key} - {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! End-of-run summary for people running the tracker by hand.
+//!
+//! Logs go to stderr and are meant for cron mail & journals; this is a short aligned table on
+//! stdout with price & stock changes colored, green when they are good news.
+
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+
+/// Whether stdout should be colored, honoring `--no-color`, <https://no-color.org> and builds
+/// without the `color` feature
+pub fn use_color(no_color: bool) -> bool {
+    cfg!(feature = "color")
+        && !no_color
+        && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && std::io::stdout().is_terminal()
+}
+
+pub struct Summary {
+    color: bool,
+    rows: Vec<(&'static str, String)>,
+}
+
+impl Summary {
+    pub const fn new(color: bool) -> Self {
+        Self {
+            color,
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(&mut self, label: &'static str, value: impl Display) {
+        self.rows.push((label, value.to_string()));
+    }
+
+    /// Adds a numeric row along with its change since the last publish
+    pub fn change(
+        &mut self,
+        label: &'static str,
+        previous: Option<i64>,
+        current: i64,
+        lower_is_better: bool,
+        format: fn(i64) -> String,
+    ) {
+        let value = match previous {
+            None => format(current),
+            Some(previous) if previous == current => {
+                format!("{} {}", format(current), self.paint(DIM, "(unchanged)"))
+            }
+            Some(previous) => {
+                let delta = current - previous;
+                let good = (delta < 0) == lower_is_better;
+                let sign = if delta < 0 { "-" } else { "+" };
+                format!(
+                    "{} {}",
+                    format(current),
+                    self.paint(
+                        if good { GREEN } else { RED },
+                        &format!("({sign}{})", format(delta.abs()))
+                    )
+                )
+            }
+        };
+        self.rows.push((label, value));
+    }
+
+    pub fn print(&self) {
+        let width = self.rows.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        for (label, value) in &self.rows {
+            println!("{} {value}", self.paint(BOLD, &format!("{label:<width$}")));
+        }
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Formats an amount the way Tokopedia shows it, e.g. Rp. 1.299.000
+pub fn format_idr(amount: i64) -> String {
+    let digits = amount.unsigned_abs().to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push('.');
+        }
+        grouped.push(digit);
+    }
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{sign}Rp. {grouped}")
+}
@@ -0,0 +1,542 @@
+//! Fetches & decodes Tokopedia product pages through the PDP GraphQL API.
+
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use log::{info, trace, warn};
+use reqwest::Client;
+use reqwest::header::{ACCEPT, HOST, HeaderMap, HeaderValue, REFERER};
+use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
+use serde_json::{Value, json};
+
+use crate::source::{Campaign, PriceSource, ProductSnapshot, Target};
+use crate::{TrackerError, model};
+
+const TKPD_GQL_ENDPOINT: &str = "https://gql.tokopedia.com/graphql/PDPGetLayoutQuery";
+const GQL_PDP_OPNAME: &str = "PDPGetLayoutQuery";
+const GQL_PDP_QUERY: &str = "fragment ProductHighlight on pdpDataProductContent {\n  name\n  price {\n    value\n    currency\n    priceFmt\n    slashPriceFmt\n    discPercentage\n    __typename\n  }\n  campaign {\n    campaignID\n    campaignType\n    campaignTypeName\n    campaignIdentifier\n    background\n    percentageAmount\n    originalPrice\n    discountedPrice\n    originalStock\n    stock\n    stockSoldPercentage\n    threshold\n    startDate\n    endDate\n    endDateUnix\n    appLinks\n    isAppsOnly\n    isActive\n    hideGimmick\n    showStockBar\n    __typename\n  }\n  thematicCampaign {\n    additionalInfo\n    background\n    campaignName\n    icon\n    __typename\n  }\n  stock {\n    useStock\n    value\n    stockWording\n    __typename\n  }\n  variant {\n    isVariant\n    parentID\n    __typename\n  }\n  wholesale {\n    minQty\n    price {\n      value\n      currency\n      __typename\n    }\n    __typename\n  }\n  isCashback {\n    percentage\n    __typename\n  }\n  isTradeIn\n  isOS\n  isPowerMerchant\n  isWishlist\n  isCOD\n  preorder {\n    duration\n    timeUnit\n    isActive\n    preorderInDays\n    __typename\n  }\n  __typename\n}\n\nfragment ProductDetail on pdpDataProductDetail {\n  content {\n    title\n    subtitle\n    __typename\n  }\n  __typename\n}\n\nquery PDPGetLayoutQuery($shopDomain: String, $productKey: String, $layoutID: String, $apiVersion: Float, $userLocation: pdpUserLocation, $extParam: String, $tokonow: pdpTokoNow, $deviceID: String) {\n  pdpGetLayout(shopDomain: $shopDomain, productKey: $productKey, layoutID: $layoutID, apiVersion: $apiVersion, userLocation: $userLocation, extParam: $extParam, tokonow: $tokonow, deviceID: $deviceID) {\n    name\n    basicInfo {\n      weight\n      weightUnit\n      condition\n      category {\n        name\n        detail {\n          name\n          __typename\n        }\n        __typename\n      }\n      __typename\n    }\n    components {\n      name\n      type\n      position\n      data {\n        ...ProductHighlight\n        ...ProductDetail\n        __typename\n      }\n      __typename\n    }\n    __typename\n  }\n}";
+const AKAMAI_HEADER: &str = "pdpGetLayout";
+/// Browser user agent Tokopedia expects, use it for the HTTP client passed to [`fetch_product`]
+pub const USER_AGENT_VALUE: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/134.0.0.0 Safari/537.36";
+
+/// An HTTP client setup Tokopedia accepts, tune connection pooling on it before building
+pub fn client_builder() -> reqwest::ClientBuilder {
+    let builder = Client::builder();
+    #[cfg(feature = "native-tls")]
+    let builder = builder.use_native_tls();
+    #[cfg(all(feature = "rustls", not(feature = "native-tls")))]
+    let builder = builder.use_rustls_tls();
+    builder
+        .user_agent(USER_AGENT_VALUE)
+        .danger_accept_invalid_certs(true) // Cringe
+        .timeout(Duration::from_secs(10))
+}
+
+/// Splits a Tokopedia product URL into its shop domain & product key
+///
+/// # Errors
+///
+/// Fails when the URL isn't a tokopedia.com product URL.
+pub fn parse_product_url(url: &str) -> Result<(String, String), TrackerError> {
+    let url = reqwest::Url::parse(url)
+        .map_err(|e| TrackerError::Url(format!("Unable to parse URL - {e}")))?;
+    if url
+        .host_str()
+        .is_none_or(|u| u != "tokopedia.com" && u != "www.tokopedia.com")
+    {
+        return Err(TrackerError::Url(format!(
+            "Wrong URL - This tool currently only supports tokopedia.com urls, got {:?}",
+            url.host_str()
+        )));
+    }
+    let Some(mut path_segment) = url.path_segments() else {
+        return Err(TrackerError::Url(
+            "Wrong URL format - Seems like you've pasted in a base URL".to_string(),
+        ));
+    };
+    let Some(shop_domain) = path_segment.next().filter(|s| !s.is_empty()) else {
+        return Err(TrackerError::Url(
+            "Wrong URL format - Shop domain is empty. Did you copy the right URL?".to_string(),
+        ));
+    };
+    let Some(product_key) = path_segment.next().filter(|s| !s.is_empty()) else {
+        return Err(TrackerError::Url(
+            "Wrong URL format - Product key is empty. Did you copy a product URL?".to_string(),
+        ));
+    };
+    Ok((shop_domain.to_string(), product_key.to_string()))
+}
+
+/// Fields [`ProductPage::into_snapshot`] reads from `product_content`, kept in step with [`model`]
+const CONTENT_FIELDS: &[&str] = &[
+    "name",
+    "price.value",
+    "price.discPercentage",
+    "stock.value",
+    "campaign.isActive",
+    "campaign.campaignTypeName",
+    "campaign.percentageAmount",
+    "campaign.discountedPrice",
+    "campaign.startDate",
+    "campaign.endDateUnix",
+    "campaign.stock",
+    "campaign.originalStock",
+];
+/// Fields [`ProductPage::into_snapshot`] reads from `basicInfo`
+const BASIC_INFO_FIELDS: &[&str] = &[
+    "weight",
+    "weightUnit",
+    "condition",
+    "category.name",
+    "category.detail",
+];
+/// Fields [`ProductPage::detail_entry`] reads from `product_detail`
+const DETAIL_FIELDS: &[&str] = &["content"];
+
+/// The `extParam` query parameter of a product URL, which carries where the listing was opened
+/// from, e.g. a flash sale page, and decides the campaign price Tokopedia returns
+#[must_use]
+pub fn ext_param(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(name, _)| name == "extParam")
+        .map(|(_, value)| value.into_owned())
+        .filter(|value| !value.is_empty())
+}
+
+/// The parts of a PDP layout response this tool reads
+#[derive(Clone, Debug, Default)]
+pub struct ProductPage {
+    /// `product_content` component: name, price, stock & campaign
+    pub content: Value,
+    /// `product_detail` component: the specification table shown under the price
+    pub detail: Value,
+    /// Layout level listing info: category, weight & condition
+    pub basic_info: Value,
+}
+
+impl ProductPage {
+    /// Looks up a row of the specification table by its (localized) title
+    #[must_use]
+    pub fn detail_entry(&self, titles: &[&str]) -> Option<&str> {
+        self.detail["content"]
+            .as_array()?
+            .iter()
+            .find(|row| {
+                row["title"]
+                    .as_str()
+                    .is_some_and(|t| titles.iter().any(|title| t.eq_ignore_ascii_case(title)))
+            })
+            .and_then(|row| row["subtitle"].as_str())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Fields this tool reads that the page doesn't have, a sign Tokopedia changed the API. Fields
+    /// of an object that is null, like the campaign when none is running, aren't missing
+    #[must_use]
+    pub fn missing_fields(&self) -> Vec<String> {
+        let parts = [
+            ("product_content", &self.content, CONTENT_FIELDS),
+            ("basicInfo", &self.basic_info, BASIC_INFO_FIELDS),
+            ("product_detail", &self.detail, DETAIL_FIELDS),
+        ];
+        let mut missing = Vec::new();
+        for (part, value, fields) in parts {
+            for field in fields {
+                if let Some(prefix) = missing_prefix(value, field) {
+                    missing.push(if prefix.is_empty() {
+                        part.to_string()
+                    } else {
+                        format!("{part}.{prefix}")
+                    });
+                }
+            }
+        }
+        // A missing object is reported once rather than for each of its fields
+        missing.dedup();
+        missing
+    }
+
+    /// Decodes the fields this tool tracks from a PDP layout, warning about missing fields
+    ///
+    /// # Errors
+    ///
+    /// Fails when the name, price or stock can't be decoded, which usually means the API changed.
+    pub fn into_snapshot(self) -> Result<ProductSnapshot, TrackerError> {
+        let missing_fields = self.missing_fields();
+        for field in &missing_fields {
+            warn!("Schema drift - Tokopedia's response has no {field}");
+        }
+
+        let decoded =
+            decode::<model::ProductContent>("product_content", &self.content).and_then(|content| {
+                let basic_info: model::BasicInfo = if self.basic_info.is_null() {
+                    model::BasicInfo::default()
+                } else {
+                    decode("basicInfo", &self.basic_info)?
+                };
+                Ok((content, basic_info))
+            });
+        let (content, basic_info) = match decoded {
+            Err(TrackerError::Decode(_)) if !missing_fields.is_empty() => {
+                return Err(TrackerError::SchemaDrift(missing_fields));
+            }
+            decoded => decoded?,
+        };
+
+        let campaign = content.campaign.map(|campaign| {
+            let active = campaign.is_active;
+            let campaign = Campaign {
+                name: campaign
+                    .campaign_type_name
+                    .filter(|c| !c.is_empty())
+                    .unwrap_or_else(|| "Campaign".to_string()),
+                starts_at: campaign.start_date.as_deref().and_then(campaign_time),
+                ends_at: campaign
+                    .end_date_unix
+                    .and_then(|end| DateTime::<Utc>::from_timestamp(end, 0)),
+                price: campaign.discounted_price,
+                discount_percent: campaign.percentage_amount,
+                stock: campaign.stock,
+                original_stock: campaign.original_stock,
+            };
+            (active, campaign)
+        });
+        // Campaigns are announced on the listing before they start
+        let (campaign, upcoming_campaign) = match campaign {
+            Some((true, campaign)) => (Some(campaign), None),
+            Some((false, campaign))
+                if campaign.starts_at.is_some_and(|start| start > Utc::now()) =>
+            {
+                (None, Some(campaign))
+            }
+            _ => (None, None),
+        };
+
+        let category_path = basic_info.category.as_ref().map(|category| {
+            category
+                .detail
+                .iter()
+                .filter_map(|level| level.name.as_deref())
+                .collect::<Vec<_>>()
+                .join(" > ")
+        });
+        let weight = basic_info
+            .weight
+            .map(|weight| match basic_info.weight_unit.as_deref() {
+                Some(unit) if unit.eq_ignore_ascii_case("KILOGRAM") => weight * 1000.0,
+                _ => weight,
+            });
+        let condition = basic_info
+            .condition
+            .as_deref()
+            .or_else(|| self.detail_entry(&["Kondisi", "Condition"]))
+            .map(str::to_lowercase);
+
+        Ok(ProductSnapshot {
+            name: content.name,
+            price: content.price.value,
+            stock: content.stock.value,
+            campaign,
+            upcoming_campaign,
+            listing_discount_percent: content.price.disc_percentage,
+            category: basic_info.category.and_then(|category| category.name),
+            category_path,
+            brand: self.detail_entry(&["Merek", "Brand"]).map(str::to_string),
+            weight,
+            dimensions: self
+                .detail_entry(&["Dimensi", "Dimensi Produk", "Ukuran", "Dimensions"])
+                .map(str::to_string),
+            condition,
+            warranty: self
+                .detail_entry(&["Garansi", "Masa Garansi", "Warranty"])
+                .map(str::to_string),
+            missing_fields,
+            raw: self.content,
+        })
+    }
+}
+
+/// Variables of the PDP layout query that pick the layout Tokopedia answers with and how it prices
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutOptions {
+    /// Layout to request, Tokopedia picks one when unset
+    pub layout_id: Option<String>,
+    pub api_version: f64,
+    /// Device the request claims to come from. Promos can differ per device, so a fixed one keeps
+    /// prices from fluctuating between runs
+    pub device_id: Option<String>,
+}
+
+impl LayoutOptions {
+    /// What Tokopedia's own product page requests
+    pub const DEFAULT: Self = Self {
+        layout_id: None,
+        api_version: 1.0,
+        device_id: None,
+    };
+}
+
+impl Default for LayoutOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Tokopedia as a [`PriceSource`]
+#[derive(Clone, Debug)]
+pub struct Tokopedia {
+    http_client: Client,
+    layout: LayoutOptions,
+    headers: HeaderMap,
+}
+
+impl Tokopedia {
+    /// Uses the client for every request, build it from [`client_builder`]
+    #[must_use]
+    pub fn new(http_client: Client) -> Self {
+        Self {
+            http_client,
+            layout: LayoutOptions::DEFAULT,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Requests another layout than the default one, for when Tokopedia rolls out a new version
+    #[must_use]
+    pub fn with_layout(mut self, layout: LayoutOptions) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sends these headers with every request, replacing the default headers of the same name
+    #[must_use]
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+}
+
+impl PriceSource for Tokopedia {
+    fn parse_url(&self, url: &str) -> Result<Target, TrackerError> {
+        let (shop, key) = parse_product_url(url)?;
+        Ok(Target {
+            shop,
+            key,
+            context: ext_param(url),
+        })
+    }
+
+    async fn fetch(&self, target: &Target) -> Result<ProductSnapshot, TrackerError> {
+        fetch_product_page(
+            &self.http_client,
+            &target.shop,
+            &target.key,
+            target.context.as_deref(),
+            &self.layout,
+            &self.headers,
+        )
+        .await?
+        .into_snapshot()
+    }
+}
+
+/// Fetches & decodes a Tokopedia listing
+///
+/// # Errors
+///
+/// Fails when the request fails, Tokopedia returns an error or the response can't be decoded.
+pub async fn fetch_product(
+    http_client: &Client,
+    shop_domain: &str,
+    product_key: &str,
+) -> Result<ProductSnapshot, TrackerError> {
+    fetch_product_page(
+        http_client,
+        shop_domain,
+        product_key,
+        None,
+        &LayoutOptions::default(),
+        &HeaderMap::new(),
+    )
+    .await?
+    .into_snapshot()
+}
+
+/// Fetches the PDP layout of a Tokopedia listing
+///
+/// The `extParam` of the URL it was opened from is sent along so campaign prices match what the
+/// browser showed. `extra_headers` replace the default headers of the same name.
+///
+/// # Errors
+///
+/// Fails when the request fails, Tokopedia returns an error or the product content is missing.
+pub async fn fetch_product_page(
+    http_client: &Client,
+    shop_domain: &str,
+    product_key: &str,
+    ext_param: Option<&str>,
+    layout: &LayoutOptions,
+    extra_headers: &HeaderMap,
+) -> Result<ProductPage, TrackerError> {
+    let mut tokopedia_query = json!({
+        "query": GQL_PDP_QUERY,
+        "operationName": GQL_PDP_OPNAME,
+        "variables": {
+            "shopDomain": shop_domain,
+            "productKey": product_key,
+            "apiVersion": layout.api_version,
+        }
+    });
+    if let Some(layout_id) = &layout.layout_id {
+        tokopedia_query["variables"]["layoutID"] = json!(layout_id);
+    }
+    if let Some(ext_param) = ext_param {
+        tokopedia_query["variables"]["extParam"] = json!(ext_param);
+    }
+    if let Some(device_id) = &layout.device_id {
+        tokopedia_query["variables"]["deviceID"] = json!(device_id);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    headers.insert(HOST, HeaderValue::from_static("gql.tokopedia.com"));
+    headers.insert(
+        REFERER,
+        HeaderValue::from_str(&format!(
+            "https://www.tokopedia.com/{shop_domain}/{product_key}"
+        ))
+        .map_err(|e| TrackerError::Url(format!("Invalid product URL - {e}")))?,
+    );
+    headers.insert("x-tkpd-akamai", HeaderValue::from_static(AKAMAI_HEADER));
+    headers.extend(extra_headers.clone());
+
+    info!("Sending Tokopedia API request");
+    let response = http_client
+        .post(TKPD_GQL_ENDPOINT)
+        .headers(headers)
+        .body(tokopedia_query.to_string())
+        .send()
+        .await?;
+
+    info!("HTTP response received!");
+    // Decoded straight from the bytes, a Value tree of every component would be several times the
+    // size of the body. The body itself is still buffered whole, components borrow from it
+    let bytes = response.bytes().await?;
+    trace!("{}", String::from_utf8_lossy(&bytes));
+    let body: model::PdpGetLayoutResponse = serde_json::from_slice(&bytes).map_err(|e| {
+        if e.is_data() {
+            TrackerError::Decode(format!("Unexpected response - {e}"))
+        } else {
+            TrackerError::Json(e)
+        }
+    })?;
+
+    // Handle Error
+    if let Some(errors) = body.errors {
+        let unknown_fields: Vec<String> = errors
+            .iter()
+            .filter_map(|error| unknown_field(error.message.as_deref()?))
+            .collect();
+        if !unknown_fields.is_empty() {
+            return Err(TrackerError::SchemaDrift(unknown_fields));
+        }
+        let message = errors.into_iter().next().map_or_else(
+            || "Ada error tapi gaada error woi".to_string(),
+            |first_error| {
+                first_error.message.unwrap_or_else(|| {
+                    "Woi ada error tapi messagenya gaada goblok ini toped".to_string()
+                })
+            },
+        );
+        return Err(TrackerError::GraphQl(message));
+    }
+
+    let Some(layout) = body.data.and_then(|data| data.pdp_get_layout) else {
+        return Err(TrackerError::NotFound(format!(
+            "Tokopedia has no listing {product_key} in shop {shop_domain}"
+        )));
+    };
+    // Only the first data object of the components read here is parsed
+    let component = |name: &str| -> Result<Option<Value>, TrackerError> {
+        let Some(data) = layout
+            .components
+            .iter()
+            .find(|c| c.name == name)
+            .and_then(|c| c.data)
+        else {
+            return Ok(None);
+        };
+        let data: Vec<&RawValue> = serde_json::from_str(data.get())
+            .map_err(|e| TrackerError::Decode(format!("Unexpected {name} - {e}")))?;
+        data.first()
+            .map(|first| serde_json::from_str(first.get()))
+            .transpose()
+            .map_err(|e| TrackerError::Decode(format!("Unexpected {name} - {e}")))
+    };
+    let Some(content) = component("product_content")? else {
+        return Err(TrackerError::Decode(
+            "Unable to fetch product content detail".to_string(),
+        ));
+    };
+
+    Ok(ProductPage {
+        content,
+        detail: component("product_detail")?.unwrap_or_default(),
+        basic_info: layout.basic_info,
+    })
+}
+
+/// Reads a campaign date, which Tokopedia sends in Jakarta time like `2025-03-01 00:00:00`
+fn campaign_time(date: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(date) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let wib = FixedOffset::east_opt(7 * 3600)?;
+    NaiveDateTime::parse_from_str(date.trim(), "%Y-%m-%d %H:%M:%S")
+        .ok()?
+        .and_local_timezone(wib)
+        .single()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// The first part of a dotted `path` that `value` doesn't have, or `None` when it has the path or
+/// an object along it is null
+fn missing_prefix(value: &Value, path: &str) -> Option<String> {
+    let mut current = value;
+    let mut prefix = Vec::new();
+    for key in path.split('.') {
+        let fields = match current {
+            Value::Null => return None,
+            Value::Object(fields) => fields,
+            // An object turned into something else
+            _ => return Some(prefix.join(".")),
+        };
+        prefix.push(key);
+        let Some(next) = fields.get(key) else {
+            return Some(prefix.join("."));
+        };
+        current = next;
+    }
+    None
+}
+
+/// The field a GraphQL validation error says doesn't exist, as `Type.field`
+fn unknown_field(message: &str) -> Option<String> {
+    // Cannot query field "name" on type "Type".
+    let mut quoted = message
+        .strip_prefix("Cannot query field ")?
+        .split('"')
+        .skip(1)
+        .step_by(2);
+    let field = quoted.next()?;
+    let on_type = quoted.next()?;
+    Some(format!("{on_type}.{field}"))
+}
+
+/// Decodes part of the response, naming the part & field when the API changed shape
+fn decode<T: DeserializeOwned>(part: &str, value: &Value) -> Result<T, TrackerError> {
+    T::deserialize(value).map_err(|e| TrackerError::Decode(format!("Unexpected {part} - {e}")))
+}
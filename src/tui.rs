@@ -0,0 +1,288 @@
+//! `tui` dashboard, a live table of every tracked product read from the broker's retained topics.
+//!
+//! The table is redrawn whenever a topic changes. The terminal is in raw mode while it is shown,
+//! keys act on the selected row right away.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+
+use chrono::{DateTime, Utc};
+use crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use log::LevelFilter;
+use rumqttc::Publish;
+use serde_json::Value;
+use tkpd_tracker::{Publisher, TrackerError, state};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+use crate::cli::{Args, OutputFormat};
+use crate::summary;
+
+const HELP: &str = "↑/↓ select · r refresh · d delete · q quit";
+
+enum Event {
+    Message(Publish),
+    Key(KeyEvent),
+}
+
+/// Raw mode, left again however the dashboard stops
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self, TrackerError> {
+        terminal::enable_raw_mode()
+            .map_err(TrackerError::io("Unable to read keys from the terminal"))?;
+        // Hides the cursor, it would only blink after the help line
+        print!("\x1b[?25l");
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+        println!("\x1b[?25h");
+    }
+}
+
+/// Shows the dashboard until `q` or the end of input
+pub async fn run(publisher: Publisher, mut args: Args) -> Result<(), TrackerError> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(TrackerError::Usage(
+            "tui needs a terminal, use list or --watch instead".to_string(),
+        ));
+    }
+    // Log lines would scroll the table away, what commands did is shown below it instead
+    log::set_max_level(LevelFilter::Off);
+    // So would the summary, JSON or raw data a refresh prints to stdout
+    args.output_format = OutputFormat::None;
+    args.dump_raw = args.dump_raw.filter(|path| path.as_os_str() != "-");
+    let args = &args;
+
+    let discovery_prefix = args.ha_mqtt_discovery_topic.as_str();
+    let mut incoming = publisher
+        .subscribe(&state::topic_filters(discovery_prefix))
+        .await?;
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+
+    let messages_tx = events_tx.clone();
+    tokio::spawn(async move {
+        loop {
+            match incoming.recv().await {
+                Ok(publish) => {
+                    if messages_tx.send(Event::Message(publish)).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    let _raw_mode = RawMode::enable()?;
+    // Reading keys blocks, so it gets a thread of its own
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let event::Event::Key(key) = event
+                && key.kind == KeyEventKind::Press
+                && events_tx.send(Event::Key(key)).is_err()
+            {
+                return;
+            }
+        }
+        let _ = events_tx.send(Event::Key(KeyCode::Char('q').into()));
+    });
+
+    let mut retained = BTreeMap::new();
+    let mut message = String::from("Reading tracked products from the broker...");
+    let mut selected = 0;
+    // The product `d` was pressed on, deleted once `y` confirms it
+    let mut deleting: Option<String> = None;
+    let mut products = draw(&retained, discovery_prefix, &message, &mut selected);
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::Message(publish) => {
+                let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                if payload.is_empty() {
+                    retained.remove(&publish.topic);
+                } else {
+                    retained.insert(publish.topic, payload);
+                }
+                // The retained replay comes in a burst, draw once it's through
+                if !events.is_empty() {
+                    continue;
+                }
+            }
+            Event::Key(key) => {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    break;
+                }
+                if let Some(hash) = deleting.take() {
+                    message = if key.code == KeyCode::Char('y') {
+                        match delete(&publisher, discovery_prefix, &hash).await {
+                            Ok(()) => format!("Deleted {hash}, backed up to the working directory"),
+                            Err(e) => format!("Deleting {hash} failed - {e}"),
+                        }
+                    } else {
+                        format!("Kept {hash}")
+                    };
+                } else {
+                    match (key.code, products.get(selected).cloned()) {
+                        (KeyCode::Up | KeyCode::Char('k'), _) => {
+                            selected = selected.saturating_sub(1);
+                        }
+                        (KeyCode::Down | KeyCode::Char('j'), _) => selected += 1,
+                        (KeyCode::Char('r'), Some(hash)) => {
+                            message = refresh(&publisher, args, &retained, &hash).await;
+                        }
+                        (KeyCode::Char('d'), Some(hash)) => {
+                            message =
+                                format!("Delete {hash}? y deletes it, any other key keeps it");
+                            deleting = Some(hash);
+                        }
+                        (KeyCode::Char('r' | 'd'), None) => {
+                            message = "No product to act on yet".to_string();
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+        products = draw(&retained, discovery_prefix, &message, &mut selected);
+    }
+
+    publisher.disconnect().await
+}
+
+/// Scrapes & publishes a product right away, from the URL its device links to
+async fn refresh(
+    publisher: &Publisher,
+    args: &Args,
+    retained: &BTreeMap<String, String>,
+    product_hash: &str,
+) -> String {
+    let discovery_prefix = args.ha_mqtt_discovery_topic.as_str();
+    let state = state::from_retained(retained.clone(), discovery_prefix);
+    let Some(url) = state["products"][product_hash]["configuration_url"].as_str() else {
+        return format!("{product_hash} has no URL to refresh from");
+    };
+    match crate::track_url(publisher, args, url).await {
+        Ok(()) => format!("Refreshed {product_hash}"),
+        Err(e) => format!("Refreshing {product_hash} failed - {e}"),
+    }
+}
+
+async fn delete(
+    publisher: &Publisher,
+    discovery_prefix: &str,
+    product_hash: &str,
+) -> Result<(), TrackerError> {
+    let retained = publisher
+        .read_retained(&state::product_topic_filters(
+            discovery_prefix,
+            product_hash,
+        ))
+        .await?;
+    crate::clear_product(publisher, &retained, discovery_prefix, product_hash).await
+}
+
+/// Clears the screen and draws the table, returning the product hashes in row order. `selected` is
+/// kept on a row that exists
+fn draw(
+    retained: &BTreeMap<String, String>,
+    discovery_prefix: &str,
+    message: &str,
+    selected: &mut usize,
+) -> Vec<String> {
+    let state = state::from_retained(retained.clone(), discovery_prefix);
+    let products: Vec<_> = state["products"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .collect();
+    *selected = (*selected).min(products.len().saturating_sub(1));
+
+    let mut screen = String::from("\x1b[2J\x1b[H");
+    let _ = writeln!(
+        screen,
+        "{:>3}  {:<8}  {:<40}  {:>16}  {:>6}  {:>9}  Last error",
+        "#", "Hash", "Name", "Price", "Stock", "Updated"
+    );
+    let mut hashes = Vec::new();
+    for (hash, product) in products {
+        let values = &product["values"];
+        let name: String = values["name"]
+            .as_str()
+            .unwrap_or("(unnamed)")
+            .chars()
+            .take(40)
+            .collect();
+        let price = values["price"]
+            .as_str()
+            .and_then(|price| price.parse().ok())
+            .map_or_else(|| "-".to_string(), summary::format_idr);
+        let updated = values["updated-at"]
+            .as_str()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .map_or_else(|| "-".to_string(), |at| age(at.with_timezone(&Utc)));
+        let row = format!(
+            "{:>3}  {hash:<8}  {name:<40}  {price:>16}  {:>6}  {updated:>9}  {}",
+            hashes.len() + 1,
+            values["stock"].as_str().unwrap_or("-"),
+            last_error(values)
+        );
+        if hashes.len() == *selected {
+            let _ = writeln!(screen, "\x1b[7m{row}\x1b[0m");
+        } else {
+            let _ = writeln!(screen, "{row}");
+        }
+        hashes.push(hash.clone());
+    }
+    if hashes.is_empty() {
+        screen.push_str("No tracked products yet\n");
+    }
+    let _ = write!(screen, "\n{message}\n{HELP}");
+
+    // Raw mode doesn't return the cursor to the start of the line on line breaks
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(screen.replace('\n', "\r\n").as_bytes())
+        .ok();
+    stdout.flush().ok();
+    hashes
+}
+
+/// The error of the last run when it failed, or that tracking is paused
+fn last_error(values: &Value) -> String {
+    if values["tracking"] == "OFF" {
+        return "(paused)".to_string();
+    }
+    let status: Value = values["status"]
+        .as_str()
+        .and_then(|status| serde_json::from_str(status).ok())
+        .unwrap_or_default();
+    if status["state"] == "failed" {
+        format!(
+            "{} ({})",
+            status["error"].as_str().unwrap_or_default(),
+            status["stage"].as_str().unwrap_or_default()
+        )
+    } else {
+        String::new()
+    }
+}
+
+/// How long ago something happened, in its largest unit
+fn age(at: DateTime<Utc>) -> String {
+    let seconds = (Utc::now() - at).num_seconds().max(0);
+    match seconds {
+        ..60 => format!("{seconds}s ago"),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86_400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86_400),
+    }
+}